@@ -3,18 +3,41 @@ use proc_macro2::{Ident, Span, TokenStream};
 use quote::{quote, ToTokens};
 use syn::spanned::Spanned;
 
-#[proc_macro_derive(Struct)]
+#[proc_macro_derive(Struct, attributes(bees))]
 pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
     let crate_ = quote!(::bees);
     let crate_internal = quote!(#crate_::derive_struct_internal);
     let input: syn::DeriveInput = syn::parse_macro_input!(input);
 
+    // Parse options
+    let mut fallible = false;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("bees") {
+            continue;
+        }
+
+        let res = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("fallible") {
+                fallible = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown `bees` option"))
+            }
+        });
+
+        if let Err(err) = res {
+            return err.into_compile_error().into();
+        }
+    }
+
     // Generate names
     let vis = input.vis;
     let wrapped_name = input.ident.to_string();
     let wrapper_name = format!("{}Ref", wrapped_name);
 
     let base_name = Ident::new(&wrapped_name, input.ident.span());
+    let wrapper_name_str = wrapper_name.clone();
     let wrapper_name = Ident::new(&wrapper_name, input.ident.span());
 
     // Generate generic signatures
@@ -80,6 +103,39 @@ pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
         syn::Fields::Unit => Vec::new(),
     };
 
+    // Wrapper helpers are prefixed so that they can't clash with an ordinary field's accessors,
+    // but a field could still be named after one, or after another field's accessor.
+    let mut method_names = vec![
+        (String::from("bees_modify"), None),
+        (String::from("bees_ptr_eq"), None),
+    ];
+
+    for (_, method_name_base, field) in &fields {
+        for method_name in [
+            method_name_base.to_string(),
+            format!("set_{method_name_base}"),
+            format!("{method_name_base}_prim_ref"),
+            format!("{method_name_base}_ref"),
+        ] {
+            if let Some((_, other)) = method_names.iter().find(|(name, _)| *name == method_name) {
+                let message = match other {
+                    Some(other) => {
+                        format!("the `{method_name}` accessor for this field clashes with the one for `{other}`")
+                    }
+                    None => format!(
+                        "the `{method_name}` accessor for this field clashes with a helper of the same name"
+                    ),
+                };
+
+                return syn::Error::new(field.span(), message)
+                    .into_compile_error()
+                    .into();
+            }
+
+            method_names.push((method_name, Some(method_name_base.to_string())));
+        }
+    }
+
     let accessors = fields
         .iter()
         .map(|(field_name, method_name_base, field)| {
@@ -96,6 +152,40 @@ pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
 			let method_name_ref =
                 Ident::new(&format!("{method_name_base}_ref"), method_name_base.span());
 
+            let get_and_set = if fallible {
+                quote! {
+                    #vis fn #method_name_get(&self) -> ::core::option::Option<#ty>
+                    where
+                        for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: #crate_internal::Copy,
+                    {
+                        #crate_::try_subfield!(self.0, #field_name).and_then(|field| field.try_read())
+                    }
+
+                    #vis fn #method_name_set(&self, value: #ty) -> bool
+                    where
+                        for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: Sized,
+                    {
+                        #crate_::try_subfield!(self.0, #field_name).is_some_and(|field| field.try_set(value))
+                    }
+                }
+            } else {
+                quote! {
+                    #vis fn #method_name_get(&self) -> #ty
+                    where
+                        for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: #crate_internal::Copy,
+                    {
+                        self.#method_name_prim_ref().read()
+                    }
+
+                    #vis fn #method_name_set(&self, value: #ty)
+                    where
+                        for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: Sized,
+                    {
+                        self.#method_name_prim_ref().write(value);
+                    }
+                }
+            };
+
             quote! {
                 #vis fn #method_name_prim_ref(&self) -> #crate_::Ref<#ty>
                 where
@@ -112,23 +202,31 @@ pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
                     #crate_::RefWrapper::from_raw(self.#method_name_prim_ref())
                 }
 
-                #vis fn #method_name_get(&self) -> #ty
-                where
-					for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: #crate_internal::Copy,
-                {
-                    self.#method_name_prim_ref().read()
-                }
-
-                #vis fn #method_name_set(&self, value: #ty)
-				where
-                    for<'__trivial> <#ty as #crate_internal::TrivialBound<'__trivial>>::Itself: Sized,
-				{
-                    self.#method_name_prim_ref().write(value);
-                }
+                #get_and_set
             }
         })
         .collect::<Vec<_>>();
 
+    let modify = if fallible {
+        quote! {
+            #vis fn bees_modify<__R>(
+                &self,
+                f: impl #crate_internal::FnOnce(&mut #base_name<#(#generic_fwd_list),*>) -> __R,
+            ) -> ::core::option::Option<__R> {
+                self.0.try_update(f)
+            }
+        }
+    } else {
+        quote! {
+            #vis fn bees_modify<__R>(
+                &self,
+                f: impl #crate_internal::FnOnce(&mut #base_name<#(#generic_fwd_list),*>) -> __R,
+            ) -> __R {
+                self.0.update(f)
+            }
+        }
+    };
+
     let output = quote! {
         #vis struct #wrapper_name<#(#generic_in_list),*>(#crate_::Ref<#base_name<#(#generic_fwd_list),*>>)
         #where_clause;
@@ -145,6 +243,50 @@ pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
             }
         }
 
+        impl<#(#generic_in_list),*> #crate_internal::fmt::Debug for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn fmt(&self, f: &mut #crate_internal::fmt::Formatter<'_>) -> #crate_internal::fmt::Result {
+                f.debug_tuple(#wrapper_name_str).field(&self.0).finish()
+            }
+        }
+
+        impl<#(#generic_in_list),*> #crate_internal::PartialEq for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl<#(#generic_in_list),*> #crate_internal::Eq for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {}
+
+        impl<#(#generic_in_list),*> #crate_internal::Ord for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn cmp(&self, other: &Self) -> #crate_internal::Ordering {
+                #crate_internal::Ord::cmp(&self.0, &other.0)
+            }
+        }
+
+        impl<#(#generic_in_list),*> #crate_internal::PartialOrd for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn partial_cmp(&self, other: &Self) -> #crate_internal::Option<#crate_internal::Ordering> {
+                #crate_internal::Option::Some(#crate_internal::Ord::cmp(self, other))
+            }
+        }
+
+        impl<#(#generic_in_list),*> #crate_internal::Hash for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn hash<__H: #crate_internal::Hasher>(&self, state: &mut __H) {
+                #crate_internal::Hash::hash(&self.0, state);
+            }
+        }
+
         impl<#(#generic_in_list),*> #crate_::Struct for #base_name<#(#generic_fwd_list),*>
         #where_clause
         {
@@ -168,9 +310,137 @@ pub fn derive_struct(input: NativeTokenStream) -> NativeTokenStream {
         impl<#(#generic_in_list),*> #wrapper_name<#(#generic_fwd_list),*>
         #where_clause
         {
+            #modify
+
+            #vis fn bees_ptr_eq(&self, other: &Self) -> bool {
+                self.0.ptr_eq(other.0)
+            }
+
             #(#accessors)*
         }
+
+        impl<#(#generic_in_list),*> #crate_::Relocate for #wrapper_name<#(#generic_fwd_list),*>
+        #where_clause
+        {
+            fn relocate(&mut self) {
+                #crate_::Relocate::relocate(&mut self.0)
+            }
+        }
+    };
+
+    output.into()
+}
+
+#[proc_macro_derive(Relocate, attributes(relocate))]
+pub fn derive_relocate(input: NativeTokenStream) -> NativeTokenStream {
+    let crate_ = quote!(::bees);
+    let input: syn::DeriveInput = syn::parse_macro_input!(input);
+
+    // Every type parameter may hold references.
+    let mut generics = input.generics.clone();
+    for para in generics.type_params_mut() {
+        para.bounds.push(syn::parse_quote!(#crate_::Relocate));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let name = &input.ident;
+
+    // Relocate every field which isn't skipped
+    let body = match &input.data {
+        syn::Data::Struct(stt) => match relocate_fields(&crate_, quote!(Self), &stt.fields) {
+            Ok((pat, calls)) => quote! {
+                let #pat = self;
+                #(#calls)*
+            },
+            Err(err) => return err.into_compile_error().into(),
+        },
+        syn::Data::Enum(enn) => {
+            let mut arms = Vec::new();
+
+            for variant in &enn.variants {
+                let variant_name = &variant.ident;
+
+                match relocate_fields(&crate_, quote!(Self::#variant_name), &variant.fields) {
+                    Ok((pat, calls)) => arms.push(quote! { #pat => { #(#calls)* } }),
+                    Err(err) => return err.into_compile_error().into(),
+                }
+            }
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        syn::Data::Union(unn) => {
+            return syn::Error::new(
+                unn.union_token.span(),
+                "Relocate cannot be derived on unions.",
+            )
+            .into_compile_error()
+            .into();
+        }
+    };
+
+    let output = quote! {
+        impl #impl_generics #crate_::Relocate for #name #ty_generics
+        #where_clause
+        {
+            #[allow(unused_variables)]
+            fn relocate(&mut self) {
+                #body
+            }
+        }
     };
 
     output.into()
 }
+
+fn relocate_fields(
+    crate_: &TokenStream,
+    path: TokenStream,
+    fields: &syn::Fields,
+) -> syn::Result<(TokenStream, Vec<TokenStream>)> {
+    let mut bindings = Vec::new();
+    let mut calls = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let mut skip = false;
+
+        for attr in &field.attrs {
+            if !attr.path().is_ident("relocate") {
+                continue;
+            }
+
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown `relocate` option"))
+                }
+            })?;
+        }
+
+        let binding = Ident::new(&format!("field_{i}"), Span::call_site());
+        let binding = if skip {
+            quote!(_)
+        } else {
+            calls.push(quote! { #crate_::Relocate::relocate(#binding); });
+            binding.to_token_stream()
+        };
+
+        bindings.push(match &field.ident {
+            Some(field_name) => quote!(#field_name: #binding),
+            None => binding,
+        });
+    }
+
+    let pat = match fields {
+        syn::Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        syn::Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        syn::Fields::Unit => path,
+    };
+
+    Ok((pat, calls))
+}
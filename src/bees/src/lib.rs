@@ -1,7 +1,19 @@
+#![cfg_attr(feature = "nightly", feature(arbitrary_self_types))]
+
 use std::{
-    cell::{Cell, UnsafeCell},
-    mem::MaybeUninit,
+    alloc::{Layout, LayoutError},
+    any::{Any, TypeId},
+    cell::{Cell, RefCell, UnsafeCell},
+    cmp::Ordering,
+    collections::{BTreeMap, VecDeque},
+    convert::Infallible,
+    error::Error,
+    fmt,
+    hash::Hash,
+    marker::PhantomData,
+    mem::{self, align_of, size_of, ManuallyDrop, MaybeUninit},
     num::NonZeroU64,
+    ops::{Bound, RangeBounds},
     ptr::NonNull,
 };
 
@@ -67,8 +79,16 @@ mod db {
         sync::atomic::{AtomicU64, Ordering::Relaxed},
     };
 
+    #[cfg(feature = "debug-tools")]
+    use std::panic::Location;
+
     use super::*;
 
+    /// Maps the generation of every live object to its slot's generation cell.
+    ///
+    /// This doubles as the relocation table `MovableRef`s are repaired through, so every operation
+    /// which moves an object to another slot (`compact`, `swap`, `sort_by`, `move_to`, ...) must
+    /// update it.
     pub(crate) fn use_object_db<R>(
         f: impl FnOnce(&mut NopHashMap<NonZeroU64, *mut u64>) -> R,
     ) -> R {
@@ -80,376 +100,7102 @@ mod db {
         OBJECT_DB.with(|v| f(&mut v.borrow_mut()))
     }
 
-    pub(crate) fn gen() -> NonZeroU64 {
-        static GEN: AtomicU64 = AtomicU64::new(1);
-        NonZeroU64::new(GEN.fetch_add(1, Relaxed)).unwrap()
+    /// Identifies an allocation of any type by its header's metadata.
+    pub(crate) type MetaPtr = NonNull<RefCell<AllocMeta>>;
+
+    pub(crate) type ChildLink = (MetaPtr, AnyAllocation);
+
+    #[derive(Default)]
+    pub(crate) struct AllocMeta {
+        pub occupied: usize,
+        pub cursor: usize,
+        pub parent: Option<MetaPtr>,
+        pub children: Vec<ChildLink>,
+        pub interned: hashbrown::HashMap<TypeId, Box<dyn Any>>,
+        /// The number of outstanding `FrozenAllocation` views.
+        pub frozen: usize,
+        /// The `Allocation::on_destroy` hooks, as a `Vec<AllocDestroyHook<T>>`.
+        pub destroy_hooks: Option<Box<dyn Any>>,
     }
 
-    pub(crate) fn alloc<T: 'static>(len: usize) -> NonNull<[Generational<T>]> {
-        NonNull::from(Box::leak(Box::from_iter(
-            (0..len).map(|_| Generational::new_empty()),
-        )))
+    #[cfg(feature = "stats")]
+    #[derive(Default)]
+    pub(crate) struct StatsEntry {
+        pub stats: Stats,
+        pub vacated: hashbrown::HashSet<usize>,
     }
 
-    pub(crate) unsafe fn realloc<T: 'static>(
-        _alloc: NonNull<[Generational<T>]>,
-        size: usize,
-    ) -> NonNull<[Generational<T>]> {
-        todo!();
+    pub(crate) fn use_deferred_queue<R>(f: impl FnOnce(&mut Vec<Box<dyn Any>>) -> R) -> R {
+        thread_local! {
+            static DEFERRED_QUEUE: RefCell<Vec<Box<dyn Any>>> = const { RefCell::new(Vec::new()) };
+        }
+
+        DEFERRED_QUEUE.with(|v| f(&mut v.borrow_mut()))
     }
 
-    pub(crate) unsafe fn dealloc<T: 'static>(_alloc: NonNull<[Generational<T>]>) {
-        // TODO
+    /// Maps the start address of every page in use to its length in bytes, its slot type, and its
+    /// allocation's header, so that a slot can be traced back to the allocation it belongs to.
+    pub(crate) type PageRegistry = BTreeMap<usize, (usize, TypeId, NonNull<u8>)>;
+
+    pub(crate) fn use_page_registry<R>(f: impl FnOnce(&mut PageRegistry) -> R) -> R {
+        thread_local! {
+            static PAGE_REGISTRY: RefCell<PageRegistry> = const { RefCell::new(BTreeMap::new()) };
+        }
+
+        PAGE_REGISTRY.with(|v| f(&mut v.borrow_mut()))
     }
-}
 
-// === Arena === //
+    pub(crate) fn find_header<T: 'static>(slot: *mut u64) -> Option<NonNull<AllocHeader<T>>> {
+        use_page_registry(|registry| {
+            let (&start, &(bytes, ty, header)) = registry.range(..=slot.addr()).next_back()?;
 
-#[derive_where(Debug, Copy, Clone)]
-pub struct Allocation<T: 'static> {
-    values: NonNull<[Generational<T>]>,
-}
+            // Projected references share their parent's slot but not its type.
+            (slot.addr() < start + bytes && ty == TypeId::of::<T>()).then_some(header.cast())
+        })
+    }
 
-impl<T> Allocation<T> {
-    pub fn new(len: usize) -> Self {
-        Self {
-            values: db::alloc(len),
+    /// Outstanding `Ref::borrow` (positive count) and `Ref::borrow_mut` (-1) guards by generation.
+    pub(crate) fn use_borrows<R>(f: impl FnOnce(&mut NopHashMap<NonZeroU64, isize>) -> R) -> R {
+        thread_local! {
+            static BORROWS: RefCell<NopHashMap<NonZeroU64, isize>> =
+                const { RefCell::new(NopHashMap::with_hasher(ConstSafeBuildHasherDefault::new())) };
         }
+
+        BORROWS.with(|v| f(&mut v.borrow_mut()))
     }
 
-    fn values(self) -> &'static [Generational<T>] {
-        unsafe { &self.values.as_ref() }
+    thread_local! {
+        static TOKEN_HELD: Cell<bool> = const { Cell::new(false) };
     }
 
-    pub fn put_with_gen(self, index: usize, gen: NonZeroU64, value: T) -> Ref<T> {
-        let slot = &self.values()[index];
+    pub(crate) fn token_held() -> bool {
+        TOKEN_HELD.with(Cell::get)
+    }
+
+    pub(crate) fn set_token_held(held: bool) {
+        TOKEN_HELD.with(|v| v.set(held));
+    }
 
-        unsafe { slot.replace(Some((gen, value))) };
+    /// Locks every token-less access to live objects out while the `Token` is held since it could
+    /// alias the references the token hands out.
+    #[track_caller]
+    pub(crate) fn assert_token_released() {
+        assert!(
+            !token_held(),
+            "attempted to access an object without the `Token` while it's held",
+        );
+    }
 
-        Ref {
-            gen,
-            gen_ptr: slot.gen.as_ptr(),
-            value: slot.value_ptr(),
+    /// Where each recently destroyed object was taken, for dangling-reference panics.
+    #[cfg(feature = "debug-tools")]
+    fn use_destruction_sites<R>(
+        f: impl FnOnce(&mut NopHashMap<NonZeroU64, &'static Location<'static>>) -> R,
+    ) -> R {
+        thread_local! {
+            static SITES: RefCell<NopHashMap<NonZeroU64, &'static Location<'static>>> =
+                const { RefCell::new(NopHashMap::with_hasher(ConstSafeBuildHasherDefault::new())) };
         }
+
+        SITES.with(|v| f(&mut v.borrow_mut()))
     }
 
-    pub fn put(self, index: usize, value: T) -> Ref<T> {
-        self.put_with_gen(index, db::gen(), value)
+    /// The number of destruction sites remembered before the oldest ones are forgotten.
+    #[cfg(feature = "debug-tools")]
+    const MAX_DESTRUCTION_SITES: usize = 1 << 16;
+
+    #[cfg(feature = "debug-tools")]
+    pub(crate) fn record_destruction(gen: NonZeroU64, site: &'static Location<'static>) {
+        use_destruction_sites(|sites| {
+            if sites.len() >= MAX_DESTRUCTION_SITES {
+                sites.clear();
+            }
+
+            sites.insert(gen, site);
+        });
     }
 
-    pub fn take(self, index: usize) -> Option<T> {
-        unsafe { self.values()[index].replace(None) }
+    #[cfg(feature = "debug-tools")]
+    pub(crate) fn destruction_site(gen: NonZeroU64) -> Option<&'static Location<'static>> {
+        use_destruction_sites(|sites| sites.get(&gen).copied())
     }
 
-    pub fn try_get(self, index: usize) -> Option<Ref<T>> {
-        let slot = &self.values()[index];
+    pub(crate) type DestroyHook = Box<dyn FnOnce()>;
 
-        if slot.is_full() {
-            Some(Ref {
-                gen: NonZeroU64::new(slot.gen()).unwrap(),
-                gen_ptr: slot.gen_ptr(),
-                value: slot.value_ptr(),
-            })
-        } else {
-            None
+    /// Hooks registered through `Ref::on_destroy` by generation.
+    pub(crate) fn use_destroy_hooks<R>(
+        f: impl FnOnce(&mut NopHashMap<NonZeroU64, Vec<DestroyHook>>) -> R,
+    ) -> R {
+        thread_local! {
+            static DESTROY_HOOKS: RefCell<NopHashMap<NonZeroU64, Vec<DestroyHook>>> =
+                const { RefCell::new(NopHashMap::with_hasher(ConstSafeBuildHasherDefault::new())) };
         }
+
+        DESTROY_HOOKS.with(|v| f(&mut v.borrow_mut()))
     }
 
-    pub fn get(self, index: usize) -> Ref<T> {
-        self.try_get(index).unwrap()
+    /// Outstanding `PinnedRef` handles by generation.
+    pub(crate) fn use_pins<R>(f: impl FnOnce(&mut NopHashMap<NonZeroU64, usize>) -> R) -> R {
+        thread_local! {
+            static PINS: RefCell<NopHashMap<NonZeroU64, usize>> =
+                const { RefCell::new(NopHashMap::with_hasher(ConstSafeBuildHasherDefault::new())) };
+        }
+
+        PINS.with(|v| f(&mut v.borrow_mut()))
     }
 
-    pub fn len(self) -> usize {
-        self.values.len()
+    pub(crate) fn is_pinned(gen: NonZeroU64) -> bool {
+        use_pins(|pins| !pins.is_empty() && pins.contains_key(&gen))
     }
 
-    pub fn dealloc(self) {
-        // Disconnect references
-        for slot in self.values() {
-            unsafe { slot.replace(None) };
+    #[track_caller]
+    pub(crate) fn assert_unpinned(gen: NonZeroU64) {
+        assert!(!is_pinned(gen), "attempted to relocate a pinned object");
+    }
+
+    /// Outstanding `Strong` handles by generation.
+    pub(crate) fn use_strong_counts<R>(
+        f: impl FnOnce(&mut NopHashMap<NonZeroU64, usize>) -> R,
+    ) -> R {
+        thread_local! {
+            static STRONG_COUNTS: RefCell<NopHashMap<NonZeroU64, usize>> =
+                const { RefCell::new(NopHashMap::with_hasher(ConstSafeBuildHasherDefault::new())) };
         }
 
-        unsafe { db::dealloc(self.values) }
+        STRONG_COUNTS.with(|v| f(&mut v.borrow_mut()))
     }
-}
 
-struct Generational<T> {
-    gen: Cell<u64>,
-    value: UnsafeCell<MaybeUninit<T>>,
-}
+    pub(crate) fn release_shared_borrow(gen: NonZeroU64) {
+        use_borrows(|borrows| {
+            let hashbrown::hash_map::Entry::Occupied(mut entry) = borrows.entry(gen) else {
+                unreachable!();
+            };
 
-impl<T> Generational<T> {
-    pub const fn new_empty() -> Self {
-        Self {
-            gen: Cell::new(0),
-            value: UnsafeCell::new(MaybeUninit::uninit()),
-        }
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        });
     }
 
-    pub fn is_full(&self) -> bool {
-        self.gen.get() != 0
+    #[track_caller]
+    pub(crate) fn assert_unborrowed(gen: NonZeroU64) {
+        assert!(
+            use_borrows(|borrows| borrows.is_empty() || !borrows.contains_key(&gen)),
+            "attempted to move or overwrite a borrowed object",
+        );
     }
 
-    pub fn gen_ptr(&self) -> *mut u64 {
-        self.gen.as_ptr()
+    #[track_caller]
+    pub(crate) fn assert_not_borrowed_mut(gen: NonZeroU64) {
+        assert!(
+            use_borrows(|borrows| borrows.is_empty() || borrows.get(&gen) != Some(&-1)),
+            "attempted to read a mutably borrowed object",
+        );
     }
 
-    pub fn gen(&self) -> u64 {
-        self.gen.get()
-    }
+    /// The generation of `Ref::dangling`, which is never issued so that resolving a dangling
+    /// reference by its generation can't find a real object.
+    pub(crate) const DANGLING_GEN: NonZeroU64 = NonZeroU64::MIN;
 
-    pub fn value_ptr(&self) -> *mut T {
-        self.value.get() as *mut T
-    }
+    /// The width of a generation. `Ref` packs its generation into the low bits of a word and uses
+    /// the rest to find its slot's generation cell.
+    pub(crate) const GEN_BITS: u32 = 48;
 
-    pub unsafe fn replace(&self, value: Option<(NonZeroU64, T)>) -> Option<T> {
-        let old = if self.is_full() {
-            db::use_object_db(|db| db.remove(&NonZeroU64::new(self.gen()).unwrap()));
+    pub(crate) const MAX_GEN: u64 = (1 << GEN_BITS) - 1;
 
-            Some(unsafe { self.value_ptr().read() })
-        } else {
-            None
-        };
+    static GEN: AtomicU64 = AtomicU64::new(DANGLING_GEN.get() + 1);
 
-        if let Some((gen, value)) = value {
-            // Replace entry in Object DB
-            db::use_object_db(|db| match db.entry(gen) {
-                hashbrown::hash_map::Entry::Occupied(_) => panic!("Reused generation {gen:?}"),
-                hashbrown::hash_map::Entry::Vacant(entry) => {
-                    entry.insert(self.gen_ptr());
-                }
-            });
+    /// The number of generations an allocation reserves from the global counter at once.
+    pub(crate) const GEN_BLOCK: usize = 1024;
 
-            self.gen.set(gen.get());
-            self.value_ptr().write(value);
-        } else {
-            self.gen.set(0);
-        }
+    /// The most ranges of generations a thread keeps track of before merging its oldest ones.
+    const MAX_LOCAL_GEN_RANGES: usize = 64;
 
-        old
-    }
-}
+    pub(crate) fn gen_many(count: usize) -> NonZeroU64 {
+        let start = GEN.fetch_add(count as u64, Relaxed);
+        let end = start + count as u64;
+        assert!(end - 1 <= MAX_GEN, "ran out of generations");
 
-impl<T> Drop for Generational<T> {
-    fn drop(&mut self) {
-        if self.is_full() {
-            unsafe { self.value_ptr().drop_in_place() };
-        }
+        use_local_gens(|ranges| match ranges.values_mut().next_back() {
+            // Back-to-back reservations extend the previous range.
+            Some(last) if *last == start => *last = end,
+            _ => {
+                ranges.insert(start, end);
+
+                if ranges.len() > MAX_LOCAL_GEN_RANGES {
+                    let (first_start, _) = ranges.pop_first().unwrap();
+                    let (_, second_end) = ranges.pop_first().unwrap();
+                    ranges.insert(first_start, second_end);
+                }
+            }
+        });
+
+        NonZeroU64::new(start).unwrap()
     }
-}
 
-// === Ref === //
+    /// Hands out `count` generations from `block`, a `(next, end)` range reserved from the global
+    /// counter, and refills it from there when it runs dry.
+    pub(crate) fn reserve_gens(block: &Cell<(u64, u64)>, count: usize) -> NonZeroU64 {
+        let (next, end) = block.get();
 
-const DANGLING_ERR: &str = "attempted to deref a dead pointer";
+        if end - next >= count.max(1) as u64 {
+            block.set((next + count as u64, end));
+            return NonZeroU64::new(next).unwrap();
+        }
 
-#[derive_where(Copy, Clone)]
-pub struct Ref<T: 'static> {
-    gen_ptr: *mut u64,
-    gen: NonZeroU64,
-    value: *mut T,
-}
+        // Large runs bypass the block so that they don't waste what's left of it.
+        if count >= GEN_BLOCK {
+            return gen_many(count);
+        }
 
-impl<T> Ref<T> {
-    #[inline(always)]
-    pub fn is_alive(self) -> bool {
-        self.gen.get() == unsafe { *self.gen_ptr }
+        let start = gen_many(GEN_BLOCK).get();
+        block.set((start + count as u64, start + GEN_BLOCK as u64));
+
+        NonZeroU64::new(start).unwrap()
     }
 
-    #[inline(always)]
-    pub fn get_unchecked(self) -> *mut T {
-        self.value
+    /// The ranges of generations reserved on this thread, as a map from their start to their end.
+    ///
+    /// The object DB only knows about this thread's objects, so this is how lookups tell an object
+    /// which is gone apart from one which lives in another thread's DB. To keep this bounded, the
+    /// oldest ranges are merged along with whatever other threads reserved between them, so
+    /// lookups of those generations report a dead object rather than panicking.
+    pub(crate) fn use_local_gens<R>(f: impl FnOnce(&mut BTreeMap<u64, u64>) -> R) -> R {
+        thread_local! {
+            static LOCAL_GENS: RefCell<BTreeMap<u64, u64>> = const { RefCell::new(BTreeMap::new()) };
+        }
+
+        LOCAL_GENS.with(|v| f(&mut v.borrow_mut()))
     }
 
-    #[inline(always)]
-    pub fn try_get(self) -> Option<*mut T> {
-        if self.is_alive() {
-            Some(self.get_unchecked())
-        } else {
-            None
+    /// Panics if `gen` was issued on another thread, where resolving it would have to consult a
+    /// DB this thread can't see.
+    #[track_caller]
+    pub(crate) fn assert_local_gen(gen: NonZeroU64) {
+        if gen == DANGLING_GEN {
+            return;
         }
+
+        let gen = gen.get();
+        let is_local = gen >= GEN.load(Relaxed)
+            || use_local_gens(|ranges| {
+                ranges
+                    .range(..=gen)
+                    .next_back()
+                    .is_some_and(|(_, &end)| gen < end)
+            });
+
+        assert!(
+            is_local,
+            "attempted to resolve an object on a thread other than the one which created it",
+        );
     }
 
-    #[inline(always)]
-    pub fn get(self) -> *mut T {
-        self.try_get().expect(DANGLING_ERR)
+    pub(crate) struct FreePool<T: 'static> {
+        pub headers: hashbrown::HashMap<u32, Vec<NonNull<AllocHeader<T>>>>,
+        pub pages: hashbrown::HashMap<u32, Vec<NonNull<Generational<T>>>>,
+        pub slice_blocks: hashbrown::HashMap<u32, Vec<NonNull<u64>>>,
     }
 
-    #[inline(always)]
-    pub fn try_read(self) -> Option<T>
-    where
-        T: Copy,
-    {
-        if let Some(ptr) = self.try_get() {
-            Some(unsafe { ptr.read() })
-        } else {
-            None
+    impl<T> Default for FreePool<T> {
+        fn default() -> Self {
+            Self {
+                headers: hashbrown::HashMap::default(),
+                pages: hashbrown::HashMap::default(),
+                slice_blocks: hashbrown::HashMap::default(),
+            }
         }
     }
 
-    #[inline(always)]
-    pub fn read(self) -> T
-    where
-        T: Copy,
-    {
-        self.try_read().expect(DANGLING_ERR)
+    pub(crate) fn use_free_pool<T: 'static, R>(f: impl FnOnce(&mut FreePool<T>) -> R) -> R {
+        thread_local! {
+            static FREE_POOL: RefCell<hashbrown::HashMap<TypeId, Box<dyn Any>>> =
+                RefCell::new(hashbrown::HashMap::default());
+        }
+
+        FREE_POOL.with(|v| {
+            f(v.borrow_mut()
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::<FreePool<T>>::default())
+                .downcast_mut()
+                .unwrap())
+        })
     }
 
-    #[inline(always)]
-    pub fn try_write(self, value: T) -> Option<T>
-    where
-        T: Sized,
-    {
-        if let Some(ptr) = self.try_get() {
-            let read = unsafe { ptr.read() };
-            unsafe { ptr.write(value) };
-            Some(read)
-        } else {
-            None
+    pub(crate) fn use_global_arena<T: 'static, R>(f: impl FnOnce(&mut Arena<T>) -> R) -> R {
+        thread_local! {
+            // Arenas are never dropped since tearing them down would touch other thread-locals
+            // which may already be gone by the time this one is destroyed.
+            static GLOBAL_ARENAS: RefCell<hashbrown::HashMap<TypeId, Box<dyn Any>>> =
+                RefCell::new(hashbrown::HashMap::default());
         }
+
+        GLOBAL_ARENAS.with(|v| {
+            f(v.borrow_mut()
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(ManuallyDrop::new(Arena::<T>::new())))
+                .downcast_mut::<ManuallyDrop<Arena<T>>>()
+                .unwrap())
+        })
     }
 
-    #[inline(always)]
-    pub fn write(self, value: T) -> T
-    where
-        T: Sized,
-    {
-        self.try_write(value).expect(DANGLING_ERR)
+    pub(crate) fn alloc<T: 'static>(len: usize) -> NonNull<AllocHeader<T>> {
+        let page_shift = AllocHeader::<T>::page_shift_for(len);
+        let recycled = use_free_pool::<T, _>(|pool| pool.headers.get_mut(&page_shift)?.pop());
+
+        let header = recycled.unwrap_or_else(|| {
+            alloc_with(page_shift, GlobalStorage, false).unwrap_or_else(|never| match never {})
+        });
+        unsafe { header.as_ref() }.set_len(len);
+
+        header
     }
 
-    #[inline(always)]
-    pub unsafe fn subfield_unchecked<U>(self, data: *mut U) -> Ref<U> {
-        Ref {
-            gen_ptr: self.gen_ptr,
-            gen: self.gen,
-            value: data,
+    pub(crate) fn alloc_with<T: 'static, B: StorageBackend + 'static>(
+        page_shift: u32,
+        backend: B,
+        foreign: bool,
+    ) -> Result<NonNull<AllocHeader<T>>, B::Error> {
+        // An all-zero `Generational` is an empty slot so we can skip initializing each slot and
+        // let the OS hand us lazily-zeroed pages instead.
+        let (layout, offset) =
+            AllocHeader::<T>::block_layout(page_shift).expect("bees: allocation size overflow");
+
+        let ptr = backend.alloc_zeroed(layout)?;
+        debug_assert_eq!(ptr.as_ptr().addr() % layout.align(), 0);
+
+        // Foreign allocations keep their backend so that the pages they grow into come from it
+        // too.
+        let backend = foreign.then(|| {
+            Box::new(move |layout| {
+                backend.alloc_zeroed(layout).unwrap_or_else(|err| {
+                    panic!("bees: storage backend failed to provide a page: {err:?}")
+                })
+            }) as PageSource
+        });
+
+        let header = ptr.cast::<AllocHeader<T>>();
+        unsafe {
+            let first_page = NonNull::new_unchecked(ptr.as_ptr().add(offset)).cast();
+            header
+                .as_ptr()
+                .write(AllocHeader::new(page_shift, first_page, backend));
+
+            // The first page stays registered for as long as the header exists, even while it
+            // sits in the pool.
+            header.as_ref().register_page(first_page);
         }
-    }
 
-    #[inline(always)]
-    pub fn get_for_macro(self, _: FuncDisambiguator) -> (Self, *mut T) {
-        (self, self.get())
+        Ok(header)
     }
 
-    pub fn wrap(self) -> T::Wrapper
-    where
-        T: Struct,
-    {
-        RefWrapper::from_raw(self)
+    pub(crate) fn alloc_page<T: 'static>(page_shift: u32) -> NonNull<Generational<T>> {
+        let recycled = use_free_pool::<T, _>(|pool| pool.pages.get_mut(&page_shift)?.pop());
+
+        recycled.unwrap_or_else(|| {
+            GlobalStorage
+                .alloc_zeroed(AllocHeader::<T>::page_layout(page_shift))
+                .unwrap_or_else(|never| match never {})
+                .cast()
+        })
     }
-}
 
-#[macro_export]
-macro_rules! subfield {
-    ($target:expr, $field:ident) => {{
-        let (target, ptr) =
-            $target.get_for_macro($crate::subfield_internals::get_func_disambiguator());
+    pub(crate) unsafe fn dealloc_page<T: 'static>(page_shift: u32, page: NonNull<Generational<T>>) {
+        debug_assert!((0..1 << page_shift).all(|i| !(*page.as_ptr().add(i)).is_full()));
 
-        let ptr = unsafe {
-            // Safety: this is a valid pointer to some data.
-            $crate::subfield_internals::addr_of_mut!((*ptr).$field)
-        };
+        // Stale `Ref`s may still read the generation of their slot so we can't hand this memory
+        // back to the global allocator. Instead, we keep it in a type-stable pool which future
+        // allocations of the same type draw from. Because generations are never reused, these
+        // stale `Ref`s will continue to observe their death.
+        use_free_pool::<T, _>(|pool| pool.pages.entry(page_shift).or_default().push(page));
+    }
 
-        unsafe {
-            // Safety: this field will not expire until the parent structure has expired.
-            target.subfield_unchecked(ptr)
+    pub(crate) unsafe fn dealloc<T: 'static>(header: NonNull<AllocHeader<T>>) {
+        // Stale `Allocation` handles may still read the header so, like pages, it is pooled
+        // rather than freed. Bumping the incarnation makes those handles refuse to touch it.
+        let header_ref = header.as_ref();
+        let page_shift = header_ref.page_shift;
+        header_ref.set_len(0);
+        header_ref.incarnation.set(header_ref.incarnation.get() + 1);
+
+        // Blocks from other backends are left to their backend. Mixing them into the pool would
+        // hand, e.g., file-backed memory to unrelated allocations.
+        if header_ref.backend.is_some() {
+            return;
         }
-    }};
+
+        use_free_pool::<T, _>(|pool| pool.headers.entry(page_shift).or_default().push(header));
+    }
 }
 
-#[doc(hidden)]
-pub mod subfield_internals {
-    use super::*;
+pub fn flush_deferred() {
+    // Dropping deferred values may defer even more values.
+    loop {
+        let queue = db::use_deferred_queue(mem::take);
+        if queue.is_empty() {
+            break;
+        }
+        drop(queue);
+    }
+}
 
-    pub use std::ptr::addr_of_mut;
+/// Moves `value` into this thread's global arena for `T`, which is created on first use and grows
+/// as needed.
+///
+/// Values spawned this way live until they're destroyed with [`Ref::destroy`]. Whatever is left when
+/// the thread exits is leaked.
+pub fn spawn<T: 'static>(value: T) -> Ref<T> {
+    db::use_global_arena(|arena| arena.push(value))
+}
 
-    #[inline(always)]
-    pub fn get_func_disambiguator() -> FuncDisambiguator {
-        FuncDisambiguator
-    }
+pub fn reserve_object_db(additional: usize) {
+    db::use_object_db(|db| db.reserve(additional));
 }
 
-mod func_disambiguator_sealed {
-    pub struct FuncDisambiguator;
+pub fn object_db_capacity() -> usize {
+    db::use_object_db(|db| db.capacity())
 }
 
-use derive_where::derive_where;
-pub(crate) use func_disambiguator_sealed::FuncDisambiguator;
+// === Storage === //
 
-// === MovableRef === //
+/// A source of memory for an allocation's slots.
+///
+/// An allocation keeps its backend for as long as it exists, asking it for the block holding its
+/// header and first page and then for every page it grows into.
+///
+/// # Safety
+///
+/// `alloc_zeroed` must return zeroed memory fitting `layout` which stays valid for the rest of the
+/// program. Storage is never handed back to its backend since stale `Ref`s may read from it at any
+/// point in the future.
+pub unsafe trait StorageBackend {
+    type Error: fmt::Debug;
 
-#[derive_where(Clone)]
-pub struct MovableRef<T> {
-    gen_ptr: *mut u64,
-    gen: NonZeroU64,
-    value: Cell<*mut T>,
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, Self::Error>;
 }
 
-impl<T> MovableRef<T> {
-    pub fn force_resolve_prim(&self) -> Ref<T> {
-        Ref {
-            gen_ptr: self.gen_ptr,
-            gen: self.gen,
-            value: self.value.get(),
+/// A foreign allocation's backend, with the storage errors its pages might hit turned into panics.
+type PageSource = Box<dyn Fn(Layout) -> NonNull<u8>>;
+
+/// The error returned by [`Allocation::try_new_with`].
+#[derive(Debug)]
+pub enum StorageError<E> {
+    /// The allocation is too large to describe with a [`Layout`].
+    Layout(LayoutError),
+    /// The backend failed to provide the storage.
+    Backend(E),
+}
+
+impl<E: fmt::Display> fmt::Display for StorageError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Layout(err) => fmt::Display::fmt(err, f),
+            StorageError::Backend(err) => fmt::Display::fmt(err, f),
         }
     }
+}
 
-    pub fn force_resolve(&self) -> T::Wrapper
-    where
-        T: Struct,
-    {
-        self.force_resolve_prim().wrap()
+impl<E: Error + 'static> Error for StorageError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StorageError::Layout(err) => Some(err),
+            StorageError::Backend(err) => Some(err),
+        }
     }
+}
 
-    pub fn repair_resolve_prim(&self) -> Ref<T> {
-        let resolved = self.force_resolve_prim();
-        if resolved.is_alive() {
-            return resolved;
-        }
+#[derive(Debug, Copy, Clone, Default)]
+pub struct GlobalStorage;
 
-        todo!();
-    }
+unsafe impl StorageBackend for GlobalStorage {
+    type Error = Infallible;
 
-    pub fn repair_resolve(&self) -> T::Wrapper
-    where
-        T: Struct,
-    {
-        self.repair_resolve_prim().wrap()
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, Self::Error> {
+        match NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) }) {
+            Some(ptr) => Ok(ptr),
+            None => std::alloc::handle_alloc_error(layout),
+        }
     }
 }
 
-// === ThinRef === //
+#[cfg(feature = "allocator-api2")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AllocatorStorage<A>(pub A);
 
-// TODO: Implement `ThinRef`
+#[cfg(feature = "allocator-api2")]
+unsafe impl<A> StorageBackend for AllocatorStorage<A>
+where
+    A: allocator_api2::alloc::Allocator + 'static,
+{
+    type Error = Infallible;
 
-// === Struct === //
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, Self::Error> {
+        let Ok(ptr) = self.0.allocate_zeroed(layout) else {
+            std::alloc::handle_alloc_error(layout);
+        };
 
-pub trait Struct: 'static {
-    type Wrapper: RefWrapper<Pointee = Self>;
+        Ok(ptr.cast())
+    }
 }
 
-pub trait RefWrapper: Copy {
-    type Pointee: 'static;
+/// Storage backed by a memory-mapped file which this backend creates and owns.
+///
+/// The file is created fresh so that its contents start out zeroed and no existing data can be
+/// clobbered. Every block is mapped from a new stretch at the end of the file, which is never
+/// truncated or removed afterwards since stale `Ref`s may still read from the mappings.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MmapStorage {
+    file: std::fs::File,
+    len: Cell<u64>,
+}
 
-    fn from_raw(raw: Ref<Self::Pointee>) -> Self;
+#[cfg(feature = "mmap")]
+impl MmapStorage {
+    /// Creates the backing file at `path`, failing if anything already exists there.
+    pub fn create(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
 
-    fn raw(self) -> Ref<Self::Pointee>;
+        Ok(Self {
+            file,
+            len: Cell::new(0),
+        })
+    }
 }
 
-// === Macros === //
+#[cfg(feature = "mmap")]
+unsafe impl StorageBackend for MmapStorage {
+    type Error = std::io::Error;
 
-pub extern crate self as bees;
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, Self::Error> {
+        const MAP_ALIGN: u64 = 4096;
 
-pub use bees_macro::Struct;
+        // Mappings are page-aligned, which is plenty for all but the most over-aligned types.
+        assert!(
+            layout.align() as u64 <= MAP_ALIGN,
+            "bees: slot alignment is too large for a memory-mapped allocation",
+        );
 
-#[doc(hidden)]
-pub mod derive_struct_internal {
-    pub use {Clone, Copy};
+        // The file is freshly created so extending it yields zeroed, empty slots.
+        let offset = self.len.get().next_multiple_of(MAP_ALIGN);
+        let len = offset + layout.size() as u64;
+        self.file.set_len(len)?;
+        self.len.set(len);
+
+        let mut map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(offset)
+                .len(layout.size())
+                .map_mut(&self.file)?
+        };
+        let ptr = NonNull::new(map.as_mut_ptr()).unwrap();
+
+        // The mapping is leaked alongside the storage.
+        mem::forget(map);
+
+        Ok(ptr)
+    }
+}
+
+// === Arena === //
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub live: usize,
+    pub empty: usize,
+    pub bytes: usize,
+    pub peak_live: usize,
+    pub gens_issued: u64,
+    pub puts: u64,
+    pub takes: u64,
+    pub reuses: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotError {
+    OutOfRange,
+    Empty,
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotError::OutOfRange => f.write_str("slot index out of range"),
+            SlotError::Empty => f.write_str("slot is empty"),
+        }
+    }
+}
+
+impl Error for SlotError {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PutErrorKind {
+    OutOfRange,
+    ReusedGeneration,
+    InvalidGeneration,
+    Full,
+}
+
+pub struct PutError<T> {
+    pub kind: PutErrorKind,
+    pub value: T,
+}
+
+impl<T> fmt::Debug for PutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PutError")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for PutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            PutErrorKind::OutOfRange => f.write_str("slot index out of range"),
+            PutErrorKind::ReusedGeneration => f.write_str("generation is already in use"),
+            PutErrorKind::InvalidGeneration => {
+                f.write_str("generation is reserved or out of range")
+            }
+            PutErrorKind::Full => f.write_str("arena is full"),
+        }
+    }
+}
+
+impl<T> Error for PutError<T> {}
+
+const MIN_PAGE_LEN: usize = 16;
+const MAX_PAGE_BYTES: usize = 1 << 20;
+
+/// Sits at the start of an allocation's first page and tracks the rest of its pages.
+///
+/// Every page of an allocation holds the same power-of-two number of slots so that growing and
+/// shrinking only ever adds or removes whole pages without moving any slots.
+///
+/// Per-allocation bookkeeping lives here too so that the hot path never has to consult a side
+/// table. This includes a block of generations reserved from the global counter, from which the
+/// allocation hands out generations without touching any shared state. Allocations don't get their
+/// own generation namespace: what they're handed depends on what every other allocation on every
+/// thread reserved before them.
+pub(crate) struct AllocHeader<T: 'static> {
+    incarnation: Cell<u64>,
+    gens: Cell<(u64, u64)>,
+    len: Cell<usize>,
+    page_shift: u32,
+    backend: Option<PageSource>,
+    pages: UnsafeCell<Vec<NonNull<Generational<T>>>>,
+    // Pages a foreign allocation shrank away from, kept for when it grows again since they can't
+    // go back to the backend or into the pool.
+    spare_pages: UnsafeCell<Vec<NonNull<Generational<T>>>>,
+    meta: RefCell<db::AllocMeta>,
+    #[cfg(feature = "stats")]
+    stats: RefCell<db::StatsEntry>,
+}
+
+impl<T> AllocHeader<T> {
+    fn new(
+        page_shift: u32,
+        first_page: NonNull<Generational<T>>,
+        backend: Option<PageSource>,
+    ) -> Self {
+        Self {
+            incarnation: Cell::new(0),
+            gens: Cell::new((0, 0)),
+            len: Cell::new(0),
+            page_shift,
+            backend,
+            pages: UnsafeCell::new(vec![first_page]),
+            spare_pages: UnsafeCell::default(),
+            meta: RefCell::default(),
+            #[cfg(feature = "stats")]
+            stats: RefCell::default(),
+        }
+    }
+
+    fn page_shift_for(len: usize) -> u32 {
+        let max_page_len = (MAX_PAGE_BYTES / size_of::<Generational<T>>()).max(MIN_PAGE_LEN);
+
+        len.max(MIN_PAGE_LEN)
+            .next_power_of_two()
+            .min(1 << max_page_len.ilog2())
+            .trailing_zeros()
+    }
+
+    fn block_layout(page_shift: u32) -> Result<(Layout, usize), LayoutError> {
+        Layout::new::<Self>().extend(Layout::array::<Generational<T>>(1 << page_shift)?)
+    }
+
+    fn page_layout(page_shift: u32) -> Layout {
+        Layout::array::<Generational<T>>(1 << page_shift).expect("bees: allocation size overflow")
+    }
+
+    fn page_len(&self) -> usize {
+        1 << self.page_shift
+    }
+
+    fn reserve_gens(&self, count: usize) -> NonZeroU64 {
+        db::reserve_gens(&self.gens, count)
+    }
+
+    fn pages(&self) -> &[NonNull<Generational<T>>] {
+        // `set_len` is the only place which mutates the list and it never runs while this is
+        // borrowed.
+        unsafe { &*self.pages.get() }
+    }
+
+    fn set_len(&self, len: usize) {
+        // The first page holds the header so it's never released.
+        let page_count = len.div_ceil(self.page_len()).max(1);
+        let pages = unsafe { &mut *self.pages.get() };
+        let spare_pages = unsafe { &mut *self.spare_pages.get() };
+
+        while pages.len() < page_count {
+            let page = match &self.backend {
+                Some(backend) => spare_pages
+                    .pop()
+                    .unwrap_or_else(|| backend(Self::page_layout(self.page_shift)).cast()),
+                None => db::alloc_page(self.page_shift),
+            };
+            self.register_page(page);
+            pages.push(page);
+        }
+
+        for page in pages.drain(page_count..) {
+            db::use_page_registry(|registry| registry.remove(&page.as_ptr().addr()));
+
+            if self.backend.is_some() {
+                spare_pages.push(page);
+            } else {
+                unsafe { db::dealloc_page(self.page_shift, page) };
+            }
+        }
+
+        self.len.set(len);
+    }
+
+    fn register_page(&self, page: NonNull<Generational<T>>) {
+        let bytes = self.page_len() * size_of::<Generational<T>>();
+        let header = NonNull::from(self).cast();
+
+        db::use_page_registry(|registry| {
+            registry.insert(page.as_ptr().addr(), (bytes, TypeId::of::<T>(), header))
+        });
+    }
+}
+
+type AllocDestroyHook<T> = Box<dyn FnMut(ObjId, &T)>;
+
+#[derive_where(Debug, Copy, Clone)]
+pub struct Allocation<T: 'static> {
+    header: NonNull<AllocHeader<T>>,
+    incarnation: u64,
+}
+
+impl<T> Allocation<T> {
+    pub fn new(len: usize) -> Self {
+        Self::try_new(len).expect("bees: allocation size overflow")
+    }
+
+    pub fn scope<R>(len: usize, f: impl FnOnce(Self) -> R) -> R {
+        // The guard tears the allocation down even if `f` panics.
+        let alloc = OwnedAllocation::new(len);
+        f(alloc.handle())
+    }
+
+    pub fn try_new(len: usize) -> Result<Self, LayoutError> {
+        Layout::array::<Generational<T>>(len)?;
+
+        Ok(Self::from_header(db::alloc(len)))
+    }
+
+    pub fn from_values(values: impl IntoIterator<Item = T>) -> (Self, Vec<Ref<T>>) {
+        let values = values.into_iter().collect::<Vec<_>>();
+        let alloc = Self::new(values.len());
+        let refs = values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| alloc.put(index, value))
+            .collect();
+
+        (alloc, refs)
+    }
+
+    #[cfg(feature = "allocator-api2")]
+    pub fn new_in<A>(len: usize, alloc: A) -> Self
+    where
+        A: allocator_api2::alloc::Allocator + 'static,
+    {
+        match Self::try_new_with(len, AllocatorStorage(alloc)) {
+            Ok(alloc) => alloc,
+            Err(StorageError::Layout(_)) => panic!("bees: allocation size overflow"),
+            Err(StorageError::Backend(never)) => match never {},
+        }
+    }
+
+    pub fn try_new_with<B: StorageBackend + 'static>(
+        len: usize,
+        backend: B,
+    ) -> Result<Self, StorageError<B::Error>> {
+        // A valid slot array is at most `isize::MAX` bytes so rounding its length up to a power
+        // of two can't overflow.
+        Layout::array::<Generational<T>>(len).map_err(StorageError::Layout)?;
+
+        // The first page covers the entire initial length so that the backend is only asked for
+        // more once the allocation grows.
+        let page_shift = len.max(MIN_PAGE_LEN).next_power_of_two().trailing_zeros();
+        AllocHeader::<T>::block_layout(page_shift).map_err(StorageError::Layout)?;
+
+        let header = db::alloc_with(page_shift, backend, true).map_err(StorageError::Backend)?;
+        let alloc = Self::from_header(header);
+        alloc.header().set_len(len);
+
+        Ok(alloc)
+    }
+
+    fn from_header(header: NonNull<AllocHeader<T>>) -> Self {
+        Self {
+            header,
+            incarnation: unsafe { header.as_ref() }.incarnation.get(),
+        }
+    }
+
+    fn header(self) -> &'static AllocHeader<T> {
+        // Headers are pooled rather than freed so this read is always valid, even for handles
+        // which outlived their allocation.
+        let header = unsafe { self.header.as_ref() };
+        assert_eq!(
+            header.incarnation.get(),
+            self.incarnation,
+            "attempted to use a deallocated allocation",
+        );
+
+        header
+    }
+
+    pub fn is_alive(self) -> bool {
+        unsafe { self.header.as_ref() }.incarnation.get() == self.incarnation
+    }
+
+    /// Issues a fresh generation from this allocation's own block of generations.
+    ///
+    /// Blocks are carved out of a single global counter so generations stay unique across
+    /// allocations, but only refilling a block touches that counter.
+    pub fn gen(self) -> NonZeroU64 {
+        self.header().reserve_gens(1)
+    }
+
+    fn try_slot(self, index: usize) -> Option<&'static Generational<T>> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let header = self.header();
+        let page = header.pages()[index >> header.page_shift];
+
+        Some(unsafe { &*page.as_ptr().add(index & (header.page_len() - 1)) })
+    }
+
+    fn slot(self, index: usize) -> &'static Generational<T> {
+        self.try_slot(index).unwrap_or_else(|| {
+            panic!(
+                "slot index {index} out of range for allocation of length {}",
+                self.len(),
+            )
+        })
+    }
+
+    fn slots(self) -> impl Iterator<Item = &'static Generational<T>> {
+        (0..self.len()).map(move |index| self.slot(index))
+    }
+
+    #[track_caller]
+    pub fn put_with_gen(self, index: usize, gen: NonZeroU64, value: T) -> Ref<T> {
+        assert!(
+            gen != db::DANGLING_GEN && gen.get() <= db::MAX_GEN,
+            "attempted to put an object with a reserved or out-of-range generation",
+        );
+        self.assert_not_frozen();
+        let slot = self.slot(index);
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| {
+            entry.stats.puts += 1;
+            if entry.vacated.remove(&index) {
+                entry.stats.reuses += 1;
+            }
+        });
+
+        let old_gen = NonZeroU64::new(slot.gen());
+
+        match (old_gen, unsafe { slot.replace(Some((gen, value))) }) {
+            (Some(old_gen), Some(old)) => self.notify_destroyed(old_gen, &old),
+            _ => {
+                let _occupied = self.with_meta(|meta| {
+                    meta.occupied += 1;
+                    meta.occupied
+                });
+
+                #[cfg(feature = "stats")]
+                self.with_stats(|entry| {
+                    entry.stats.peak_live = entry.stats.peak_live.max(_occupied)
+                });
+            }
+        }
+
+        slot.make_ref(gen)
+    }
+
+    pub fn put(self, index: usize, value: T) -> Ref<T> {
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| entry.stats.gens_issued += 1);
+
+        self.put_with_gen(index, self.gen(), value)
+    }
+
+    pub fn put_with(self, index: usize, f: impl FnOnce(Ref<T>) -> T) -> Ref<T> {
+        // The reference handed to `f` only becomes alive once the value is put.
+        let gen = self.gen();
+        let value = f(self.ref_for(index, gen));
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| entry.stats.gens_issued += 1);
+
+        self.put_with_gen(index, gen, value)
+    }
+
+    pub fn put_many(self, entries: impl IntoIterator<Item = (usize, T)>) -> Vec<Ref<T>> {
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        assert!(
+            entries.iter().all(|&(index, _)| index < self.len()),
+            "slot index out of range",
+        );
+
+        // Every precondition is checked before the first slot is written so that a panic can't
+        // leave the batch half applied.
+        self.assert_not_frozen();
+        for &(index, _) in &entries {
+            self.slot(index).assert_unborrowed();
+        }
+
+        #[cfg(feature = "stats")]
+        let indices = entries.iter().map(|&(index, _)| index).collect::<Vec<_>>();
+
+        // Reserve a contiguous run of generations and fill the slots under a single DB borrow.
+        let first_gen = self.header().reserve_gens(entries.len());
+        let mut refs = Vec::with_capacity(entries.len());
+        let mut displaced = Vec::new();
+
+        db::use_object_db(|db| {
+            for (offset, (index, value)) in entries.into_iter().enumerate() {
+                let gen = first_gen.checked_add(offset as u64).unwrap();
+
+                let slot = self.slot(index);
+                let old_gen = NonZeroU64::new(slot.gen());
+
+                if let Some(old) = unsafe { slot.replace_in(db, Some((gen, value))) } {
+                    displaced.push((old_gen.unwrap(), old));
+                }
+
+                refs.push(self.ref_for(index, gen));
+            }
+        });
+
+        let filled = refs.len() - displaced.len();
+        let _occupied = self.with_meta(|meta| {
+            meta.occupied += filled;
+            meta.occupied
+        });
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| {
+            entry.stats.gens_issued += indices.len() as u64;
+            entry.stats.puts += indices.len() as u64;
+            entry.stats.peak_live = entry.stats.peak_live.max(_occupied);
+
+            for index in &indices {
+                if entry.vacated.remove(index) {
+                    entry.stats.reuses += 1;
+                }
+            }
+        });
+
+        // Hooks and destructors run last since they may panic.
+        for (old_gen, old) in &displaced {
+            self.notify_destroyed(*old_gen, old);
+        }
+        drop(displaced);
+
+        refs
+    }
+
+    #[track_caller]
+    pub fn take_many(self, indices: impl IntoIterator<Item = usize>) -> Vec<Option<T>> {
+        let indices = indices.into_iter().collect::<Vec<_>>();
+        assert!(
+            indices.iter().all(|&index| index < self.len()),
+            "slot index out of range",
+        );
+
+        // As in `put_many`, nothing is written until every slot is known to be takeable.
+        for &index in &indices {
+            self.slot(index).assert_unborrowed();
+        }
+
+        #[cfg(feature = "debug-tools")]
+        for &index in &indices {
+            if let Some(gen) = NonZeroU64::new(self.slot(index).gen()) {
+                db::record_destruction(gen, std::panic::Location::caller());
+            }
+        }
+
+        let gens = indices
+            .iter()
+            .map(|&index| NonZeroU64::new(self.slot(index).gen()))
+            .collect::<Vec<_>>();
+
+        let taken = db::use_object_db(|db| {
+            indices
+                .iter()
+                .map(|&index| unsafe { self.slot(index).replace_in(db, None) })
+                .collect::<Vec<_>>()
+        });
+
+        let emptied = taken.iter().filter(|value| value.is_some()).count();
+        self.with_meta(|meta| meta.occupied -= emptied);
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| {
+            for (&index, value) in indices.iter().zip(&taken) {
+                if value.is_some() {
+                    entry.stats.takes += 1;
+                    entry.vacated.insert(index);
+                }
+            }
+        });
+
+        for (gen, value) in gens.into_iter().zip(&taken) {
+            if let (Some(gen), Some(value)) = (gen, value) {
+                self.notify_destroyed(gen, value);
+            }
+        }
+
+        taken
+    }
+
+    pub fn try_put_with_gen(
+        self,
+        index: usize,
+        gen: NonZeroU64,
+        value: T,
+    ) -> Result<Ref<T>, PutError<T>> {
+        let Some(slot) = self.try_slot(index) else {
+            return Err(PutError {
+                kind: PutErrorKind::OutOfRange,
+                value,
+            });
+        };
+
+        if gen == db::DANGLING_GEN || gen.get() > db::MAX_GEN {
+            return Err(PutError {
+                kind: PutErrorKind::InvalidGeneration,
+                value,
+            });
+        }
+
+        if slot.gen() != gen.get() && db::use_object_db(|db| db.contains_key(&gen)) {
+            return Err(PutError {
+                kind: PutErrorKind::ReusedGeneration,
+                value,
+            });
+        }
+
+        Ok(self.put_with_gen(index, gen, value))
+    }
+
+    pub fn try_put(self, index: usize, value: T) -> Result<Ref<T>, PutError<T>> {
+        if index >= self.len() {
+            return Err(PutError {
+                kind: PutErrorKind::OutOfRange,
+                value,
+            });
+        }
+
+        Ok(self.put(index, value))
+    }
+
+    #[track_caller]
+    pub fn take(self, index: usize) -> Option<T> {
+        #[cfg(feature = "debug-tools")]
+        if let Some(gen) = NonZeroU64::new(self.slot(index).gen()) {
+            db::record_destruction(gen, std::panic::Location::caller());
+        }
+
+        let gen = NonZeroU64::new(self.slot(index).gen());
+        let taken = unsafe { self.slot(index).replace(None) };
+
+        if let (Some(gen), Some(value)) = (gen, &taken) {
+            self.with_meta(|meta| meta.occupied -= 1);
+            self.notify_destroyed(gen, value);
+        }
+
+        #[cfg(feature = "stats")]
+        if taken.is_some() {
+            self.with_stats(|entry| {
+                entry.stats.takes += 1;
+                entry.vacated.insert(index);
+            });
+        }
+
+        taken
+    }
+
+    /// Registers `hook` to run whenever an object in this allocation is destroyed, whether it's
+    /// taken, overwritten, or dropped along with the allocation.
+    pub fn on_destroy(self, hook: impl FnMut(ObjId, &T) + 'static) {
+        self.with_meta(|meta| {
+            meta.destroy_hooks
+                .get_or_insert_with(|| Box::new(Vec::<AllocDestroyHook<T>>::new()))
+                .downcast_mut::<Vec<AllocDestroyHook<T>>>()
+                .unwrap()
+                .push(Box::new(hook));
+        });
+    }
+
+    fn notify_destroyed(self, gen: NonZeroU64, value: &T) {
+        // Hooks run without any of the tables borrowed so they're free to use the allocation.
+        let mut hooks = self.with_meta(|meta| meta.destroy_hooks.take());
+        Self::run_destroy_hooks(&mut hooks, gen, value);
+
+        if let Some(mut hooks) = hooks {
+            self.with_meta(|meta| {
+                if let Some(added) = meta.destroy_hooks.take() {
+                    let added = added.downcast::<Vec<AllocDestroyHook<T>>>().unwrap();
+                    hooks
+                        .downcast_mut::<Vec<AllocDestroyHook<T>>>()
+                        .unwrap()
+                        .extend(*added);
+                }
+
+                meta.destroy_hooks = Some(hooks);
+            });
+        }
+    }
+
+    fn run_destroy_hooks(hooks: &mut Option<Box<dyn Any>>, gen: NonZeroU64, value: &T) {
+        let object_hooks = db::use_destroy_hooks(|hooks| {
+            if hooks.is_empty() {
+                None
+            } else {
+                hooks.remove(&gen)
+            }
+        });
+
+        for hook in object_hooks.into_iter().flatten() {
+            hook();
+        }
+
+        if let Some(hooks) = hooks {
+            for hook in hooks.downcast_mut::<Vec<AllocDestroyHook<T>>>().unwrap() {
+                hook(ObjId(gen), value);
+            }
+        }
+    }
+
+    pub fn insert(self, value: T) -> Option<Ref<T>> {
+        let index = self.next_free()?;
+        Some(self.put(index, value))
+    }
+
+    pub fn remaining_capacity(self) -> usize {
+        self.len() - self.with_meta(|meta| meta.occupied)
+    }
+
+    fn with_meta<R>(self, f: impl FnOnce(&mut db::AllocMeta) -> R) -> R {
+        f(&mut self.header().meta.borrow_mut())
+    }
+
+    fn meta_ptr(self) -> db::MetaPtr {
+        NonNull::from(&self.header().meta)
+    }
+
+    pub fn index_of(self, value: Ref<T>) -> Option<usize> {
+        if !value.is_alive() {
+            return None;
+        }
+
+        self.slot_index(value.gen_ptr().as_ptr())
+    }
+
+    pub fn contains(self, value: Ref<T>) -> bool {
+        self.index_of(value).is_some()
+    }
+
+    #[track_caller]
+    pub fn take_ref(self, value: Ref<T>) -> Option<T> {
+        self.debug_assert_owns(value);
+        self.take(self.index_of(value)?)
+    }
+
+    /// Catches references from other allocations being passed in by mistake, which would otherwise
+    /// be quietly treated as dead.
+    #[track_caller]
+    fn debug_assert_owns(self, value: Ref<T>) {
+        #[cfg(debug_assertions)]
+        if let Some(owner) = value.allocation() {
+            assert_eq!(
+                owner.header, self.header,
+                "attempted to use a reference into another allocation",
+            );
+        }
+
+        #[cfg(not(debug_assertions))]
+        let _ = value;
+    }
+
+    fn slot_index(self, gen_ptr: *mut u64) -> Option<usize> {
+        let header = self.header();
+        let index = header
+            .pages()
+            .iter()
+            .enumerate()
+            .find_map(|(page_index, page)| {
+                let offset = gen_ptr.addr().checked_sub(page.as_ptr().addr())?;
+                let index = offset / size_of::<Generational<T>>();
+
+                (index < header.page_len()).then_some(page_index * header.page_len() + index)
+            })?;
+
+        (self.try_slot(index)?.gen_ptr() == gen_ptr).then_some(index)
+    }
+
+    pub fn is_occupied(self, index: usize) -> bool {
+        self.slot(index).is_full()
+    }
+
+    pub fn first_free(self) -> Option<usize> {
+        self.iter_free().next()
+    }
+
+    pub fn iter_free(self) -> impl Iterator<Item = usize> {
+        (0..self.len()).filter(move |&index| !self.slot(index).is_full())
+    }
+
+    fn next_free(self) -> Option<usize> {
+        if self.remaining_capacity() == 0 {
+            return None;
+        }
+
+        // Next-fit search starting from wherever the last search left off.
+        let start = self.with_meta(|meta| meta.cursor).min(self.len());
+        let index = (start..self.len())
+            .chain(0..start)
+            .find(|&index| !self.slot(index).is_full())?;
+
+        self.with_meta(|meta| meta.cursor = index + 1);
+        Some(index)
+    }
+
+    pub fn defer_take(self, index: usize) -> bool {
+        let Some(value) = self.take(index) else {
+            return false;
+        };
+
+        db::use_deferred_queue(|queue| queue.push(Box::new(value)));
+        true
+    }
+
+    #[cfg(feature = "stats")]
+    fn with_stats<R>(self, f: impl FnOnce(&mut db::StatsEntry) -> R) -> R {
+        f(&mut self.header().stats.borrow_mut())
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(self) -> Stats {
+        let live = self.len() - self.remaining_capacity();
+
+        Stats {
+            live,
+            empty: self.len() - live,
+            bytes: self.header().pages().len()
+                * self.header().page_len()
+                * size_of::<Generational<T>>(),
+            ..self.with_stats(|entry| entry.stats)
+        }
+    }
+
+    pub fn try_get(self, index: usize) -> Option<Ref<T>> {
+        let slot = self.try_slot(index)?;
+
+        Some(slot.make_ref(NonZeroU64::new(slot.gen())?))
+    }
+
+    pub fn checked_get(self, index: usize) -> Result<Ref<T>, SlotError> {
+        if index >= self.len() {
+            return Err(SlotError::OutOfRange);
+        }
+
+        self.try_get(index).ok_or(SlotError::Empty)
+    }
+
+    pub fn get_many<const N: usize>(self, indices: [usize; N]) -> Option<[Ref<T>; N]> {
+        for (i, &index) in indices.iter().enumerate() {
+            if indices[..i].contains(&index) || self.try_get(index).is_none() {
+                return None;
+            }
+        }
+
+        Some(indices.map(|index| self.get(index)))
+    }
+
+    pub fn get(self, index: usize) -> Ref<T> {
+        self.try_get(index).unwrap()
+    }
+
+    pub fn len(self) -> usize {
+        self.header().len.get()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn grow(self, new_len: usize) {
+        assert!(
+            new_len >= self.len(),
+            "cannot grow an allocation of length {} to a smaller length {new_len}",
+            self.len(),
+        );
+
+        Layout::array::<Generational<T>>(new_len).expect("bees: allocation size overflow");
+
+        // New slots live in freshly appended pages so nothing has to move.
+        self.header().set_len(new_len);
+    }
+
+    pub fn shrink_to_fit(self) {
+        let new_len = (0..self.len())
+            .rposition(|index| self.slot(index).is_full())
+            .map_or(0, |index| index + 1);
+
+        self.header().set_len(new_len);
+    }
+
+    /// Moves every live object into the lowest free slot before it, leaving pinned objects in
+    /// place, and returns the `(from, to)` indices of every live object.
+    pub fn compact(self) -> Vec<(usize, usize)> {
+        let mut to = 0;
+        let moved = (0..self.len())
+            .filter(|&index| self.slot(index).is_full())
+            .map(|from| {
+                let slot = self.slot(from);
+                if db::is_pinned(NonZeroU64::new(slot.gen()).unwrap()) {
+                    return (from, from);
+                }
+
+                // Only pinned objects can be left behind before `from`.
+                while to < from && self.slot(to).is_full() {
+                    to += 1;
+                }
+
+                if to == from {
+                    to += 1;
+                    return (from, from);
+                }
+
+                unsafe { slot.move_into(self.slot(to)) };
+                to += 1;
+                (from, to - 1)
+            })
+            .collect();
+
+        self.shrink_to_fit();
+        self.with_meta(|meta| meta.cursor = 0);
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| entry.vacated.clear());
+
+        moved
+    }
+
+    /// Like [`compact`](Self::compact) but then [relocates](Relocate) every live value so the
+    /// references they hold to objects which moved follow them.
+    ///
+    /// Values are moved out of their slots while they're relocated so their slots read as empty
+    /// to anything the hooks call into.
+    pub fn compact_relocating(self) -> Vec<(usize, usize)>
+    where
+        T: Relocate,
+    {
+        let moved = self.compact();
+
+        let mut detached = Detached::new(self, 0..self.len());
+        for (_, value) in &mut detached.values {
+            value.relocate();
+        }
+
+        moved
+    }
+
+    pub fn add_child<U>(self, child: Allocation<U>) {
+        let parent_ptr = self.meta_ptr();
+        let child_ptr = child.meta_ptr();
+
+        // Headers are never freed so every link in the chain is still readable.
+        let mut is_ancestor = false;
+        let mut curr = Some(parent_ptr);
+        while let Some(ptr) = curr {
+            if ptr == child_ptr {
+                is_ancestor = true;
+                break;
+            }
+            curr = unsafe { ptr.as_ref() }.borrow().parent;
+        }
+
+        assert!(
+            !is_ancestor,
+            "attempted to make an allocation a child of itself or of its descendants",
+        );
+
+        assert!(
+            child
+                .with_meta(|meta| meta.parent.replace(parent_ptr))
+                .is_none(),
+            "allocation already has a parent",
+        );
+
+        self.with_meta(|meta| meta.children.push((child_ptr, child.into())));
+    }
+
+    pub fn new_child<U>(self, len: usize) -> Allocation<U> {
+        let child = Allocation::new(len);
+        self.add_child(child);
+        child
+    }
+
+    /// Decomposes the allocation into a pointer to its first slot and its length, returning `None`
+    /// if it has grown past its first page.
+    ///
+    /// Slots are stored in pages of [`Allocation::page_len`] slots, so only allocations which fit
+    /// in a single page have all of their slots contiguous with the returned pointer.
+    pub fn try_into_raw_parts(self) -> Option<(NonNull<Generational<T>>, usize)> {
+        (self.len() <= self.page_len()).then(|| (self.header().pages()[0], self.len()))
+    }
+
+    #[track_caller]
+    pub fn into_raw_parts(self) -> (NonNull<Generational<T>>, usize) {
+        self.try_into_raw_parts()
+            .expect("attempted to decompose an allocation spanning several pages")
+    }
+
+    /// Reconstructs an allocation from the parts returned by [`Allocation::into_raw_parts`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must have been obtained from `into_raw_parts` on an allocation which has not
+    /// since been deallocated, and the slots' generations must not have been tampered with.
+    pub unsafe fn from_raw_parts(ptr: NonNull<Generational<T>>, len: usize) -> Self {
+        let (_, offset) = AllocHeader::<T>::block_layout(0).unwrap();
+        let alloc =
+            Self::from_header(NonNull::new_unchecked(ptr.as_ptr().cast::<u8>().sub(offset)).cast());
+        debug_assert_eq!(alloc.len(), len);
+
+        alloc
+    }
+
+    pub fn page_len(self) -> usize {
+        self.header().page_len()
+    }
+
+    /// Borrows every live object in the allocation and blocks puts into it until the returned view
+    /// is dropped, returning `None` if any of its objects are mutably borrowed.
+    pub fn try_freeze(self) -> Option<FrozenAllocation<T>> {
+        db::assert_token_released();
+
+        let gens = self
+            .slots()
+            .filter_map(|slot| NonZeroU64::new(slot.gen()))
+            .collect::<Vec<_>>();
+
+        db::use_borrows(|borrows| {
+            if gens.iter().any(|gen| borrows.get(gen) == Some(&-1)) {
+                return None;
+            }
+
+            for &gen in &gens {
+                *borrows.entry(gen).or_insert(0) += 1;
+            }
+
+            Some(())
+        })?;
+
+        self.with_meta(|meta| meta.frozen += 1);
+
+        Some(FrozenAllocation {
+            alloc: self,
+            gens,
+            _not_send: PhantomData,
+        })
+    }
+
+    #[track_caller]
+    pub fn freeze(self) -> FrozenAllocation<T> {
+        self.try_freeze()
+            .expect("attempted to freeze an allocation with mutably borrowed objects")
+    }
+
+    #[track_caller]
+    fn assert_not_frozen(self) {
+        assert_eq!(
+            self.with_meta(|meta| meta.frozen),
+            0,
+            "attempted to put into a frozen allocation",
+        );
+    }
+
+    /// Borrows this handle as a [`View`] whose references can't escape it.
+    pub fn view(&mut self) -> View<'_, T> {
+        View {
+            alloc: *self,
+            _borrow: PhantomData,
+        }
+    }
+
+    pub fn iter_alive(self) -> impl Iterator<Item = (usize, Ref<T>)> {
+        (0..self.len()).filter_map(move |index| Some((index, self.try_get(index)?)))
+    }
+
+    pub fn iter_values(self) -> impl Iterator<Item = (usize, T)>
+    where
+        T: Copy,
+    {
+        self.iter_alive()
+            .map(|(index, value)| (index, value.read()))
+    }
+
+    pub fn bump_all_generations(self) -> Vec<Ref<T>> {
+        let refs = self
+            .slots()
+            .filter(|slot| slot.is_full())
+            .map(|slot| {
+                let gen = self.gen();
+                slot.rebrand(gen);
+                slot.make_ref(gen)
+            })
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "stats")]
+        self.with_stats(|entry| entry.stats.gens_issued += refs.len() as u64);
+
+        refs
+    }
+
+    pub fn retain(self, mut pred: impl FnMut(usize, Ref<T>) -> bool) {
+        for (index, value) in self.iter_alive() {
+            if !pred(index, value) {
+                self.take(index);
+            }
+        }
+    }
+
+    pub fn snapshot(self) -> Self
+    where
+        T: Clone,
+    {
+        self.snapshot_with(|value, _| value.clone())
+    }
+
+    pub fn snapshot_with(self, mut clone: impl FnMut(&T, &SnapshotMap<T>) -> T) -> Self {
+        let detached = Detached::new(self, 0..self.len());
+
+        let mut map = SnapshotMap {
+            from: self,
+            to: Self::new(self.len()),
+            gens: vec![None; self.len()],
+        };
+
+        for (&index, &(gen, _)) in detached.indices.iter().zip(&detached.values) {
+            map.gens[index] = Some((gen, map.to.gen()));
+        }
+
+        for (&index, (_, value)) in detached.indices.iter().zip(&detached.values) {
+            let value = clone(value, &map);
+            map.to
+                .put_with_gen(index, map.gens[index].unwrap().1, value);
+        }
+
+        map.to
+    }
+
+    pub fn duplicate(self, index: usize) -> Option<Ref<T>>
+    where
+        T: Clone,
+    {
+        self.duplicate_with(index, T::clone)
+    }
+
+    /// Clones the value at `index` into the next free slot, giving the copy a fresh generation.
+    ///
+    /// Returns `None` if the slot at `index` is empty or there are no free slots left.
+    pub fn duplicate_with(self, index: usize, clone: impl FnOnce(&T) -> T) -> Option<Ref<T>> {
+        if self.remaining_capacity() == 0 {
+            return None;
+        }
+
+        let detached = Detached::new(self, [index]);
+        let (_, value) = detached.values.first()?;
+        let value = clone(value);
+        drop(detached);
+
+        self.insert(value)
+    }
+
+    fn ref_for(self, index: usize, gen: NonZeroU64) -> Ref<T> {
+        self.slot(index).make_ref(gen)
+    }
+
+    pub fn swap(self, a: usize, b: usize) {
+        self.slot(a).swap_with(self.slot(b));
+    }
+
+    /// Moves the object at `src_index` into the empty slot `dst_index` of `dst`, returning its new
+    /// reference or `None` if there was nothing to move.
+    ///
+    /// The object keeps its generation so [`MovableRef`]s, [`ThinRef`]s, and everything else
+    /// resolved through the object DB keep finding it. Moving a borrowed or pinned object panics.
+    #[track_caller]
+    pub fn move_to(self, src_index: usize, dst: Allocation<T>, dst_index: usize) -> Option<Ref<T>> {
+        let src = self.slot(src_index);
+        let target = dst.slot(dst_index);
+        let gen = NonZeroU64::new(src.gen())?;
+
+        assert!(!target.is_full(), "attempted to move into an occupied slot");
+        dst.assert_not_frozen();
+
+        unsafe { src.move_into(target) };
+
+        self.with_meta(|meta| meta.occupied -= 1);
+        let _occupied = dst.with_meta(|meta| {
+            meta.occupied += 1;
+            meta.occupied
+        });
+
+        #[cfg(feature = "stats")]
+        {
+            self.with_stats(|entry| entry.vacated.insert(src_index));
+            dst.with_stats(|entry| {
+                entry.vacated.remove(&dst_index);
+                entry.stats.peak_live = entry.stats.peak_live.max(_occupied);
+            });
+        }
+
+        Some(dst.ref_for(dst_index, gen))
+    }
+
+    #[track_caller]
+    pub fn sort_by(self, mut compare: impl FnMut(&T, &T) -> Ordering) {
+        for slot in self.slots() {
+            if let Some(gen) = NonZeroU64::new(slot.gen()) {
+                db::assert_unpinned(gen);
+            }
+        }
+
+        // Sorting only ever permutes the detached values, even if `compare` panics, so they can
+        // always be written back into the live slots.
+        let mut detached = Detached::new(self, 0..self.len());
+        detached.values.sort_by(|(_, a), (_, b)| compare(a, b));
+    }
+
+    pub fn sort_by_key<K: Ord>(self, mut key: impl FnMut(&T) -> K) {
+        self.sort_by(|a, b| key(a).cmp(&key(b)));
+    }
+
+    pub fn clear(self) {
+        for index in 0..self.len() {
+            self.take(index);
+        }
+    }
+
+    pub fn drain(self) -> Drain<T> {
+        Drain {
+            alloc: self,
+            index: 0,
+        }
+    }
+
+    pub fn extract_if(
+        self,
+        mut pred: impl FnMut(&mut T) -> bool,
+    ) -> impl Iterator<Item = (usize, T)> {
+        (0..self.len()).filter_map(move |index| {
+            let mut detached = Detached::new(self, [index]);
+            let (_, value) = detached.values.first_mut()?;
+            let extract = pred(value);
+            drop(detached);
+
+            if extract {
+                self.take(index).map(|value| (index, value))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn get_or_insert_by_key<K>(self, key: K, value: impl FnOnce() -> T) -> Ref<T>
+    where
+        K: 'static + Eq + Hash,
+    {
+        let existing = self.with_meta(|meta| {
+            meta.interned
+                .get(&TypeId::of::<K>())
+                .and_then(|map| map.downcast_ref::<hashbrown::HashMap<K, Ref<T>>>())
+                .and_then(|map| map.get(&key).copied())
+        });
+
+        if let Some(existing) = existing.filter(|r| r.is_alive()) {
+            return existing;
+        }
+
+        let inserted = self
+            .insert(value())
+            .expect("no free slots left in allocation to intern value");
+
+        self.with_meta(|meta| {
+            meta.interned
+                .entry(TypeId::of::<K>())
+                .or_insert_with(|| Box::new(hashbrown::HashMap::<K, Ref<T>>::new()))
+                .downcast_mut::<hashbrown::HashMap<K, Ref<T>>>()
+                .unwrap()
+                .insert(key, inserted);
+        });
+
+        inserted
+    }
+
+    pub fn with_auto_reclaim(self) -> ReclaimingAllocation<T> {
+        ReclaimingAllocation { alloc: Some(self) }
+    }
+
+    /// Destroys every object in the allocation and releases its storage.
+    ///
+    /// This takes time linear in the allocation's length. A reference is alive exactly when its
+    /// slot's generation matches its own, so every slot has to be cleared for outstanding
+    /// references to see their objects die. The values are only dropped after every slot has been
+    /// cleared.
+    #[track_caller]
+    pub fn dealloc(self) {
+        drop(self.dealloc_into());
+    }
+
+    /// Deallocates the allocation, handing back its surviving values in slot order rather than
+    /// dropping them.
+    ///
+    /// Values are only moved out once every slot has been disconnected so references to them are
+    /// already dead by the time the caller sees them.
+    #[track_caller]
+    pub fn dealloc_into(self) -> Vec<T> {
+        self.assert_deallocable();
+
+        let self_meta = self.meta_ptr();
+
+        // Reset the header's bookkeeping for its next user. Interned keys are forgotten here but
+        // dropped only once we're no longer borrowing the metadata.
+        let meta = self.with_meta(mem::take);
+
+        // Tear down children first since their objects may reference ours.
+        for (_, child) in meta.children.into_iter().rev() {
+            child.dealloc();
+        }
+
+        if let Some(parent) = meta.parent {
+            let mut parent = unsafe { parent.as_ref() }.borrow_mut();
+            if let Some(index) = parent
+                .children
+                .iter()
+                .position(|&(child, _)| child == self_meta)
+            {
+                parent.children.remove(index);
+            }
+        }
+
+        // Disconnect references in a single pass over the object DB, deferring the drops until
+        // we're no longer borrowing it.
+        let values = db::use_object_db(|db| {
+            self.slots()
+                .filter_map(|slot| {
+                    let gen = NonZeroU64::new(slot.gen())?;
+                    Some((gen, unsafe { slot.disconnect(db) }?))
+                })
+                .collect::<Vec<_>>()
+        });
+        drop(meta.interned);
+
+        let mut hooks = meta.destroy_hooks;
+        for (gen, value) in &values {
+            Self::run_destroy_hooks(&mut hooks, *gen, value);
+        }
+        drop(hooks);
+
+        let values = values.into_iter().map(|(_, value)| value).collect();
+
+        #[cfg(feature = "stats")]
+        self.with_stats(mem::take);
+
+        // Checks that we weren't deallocated already, which would pool the header twice.
+        self.header();
+        unsafe { db::dealloc(self.header) }
+
+        values
+    }
+}
+
+impl<T> Allocation<T> {
+    /// Panics unless the allocation and all of its descendants can be torn down, so that
+    /// deallocation never stops halfway through.
+    #[track_caller]
+    fn assert_deallocable(self) {
+        let (frozen, occupied, children) = self.with_meta(|meta| {
+            let children = meta.children.iter().map(|&(_, child)| child);
+            (meta.frozen, meta.occupied, children.collect::<Vec<_>>())
+        });
+
+        assert_eq!(frozen, 0, "attempted to deallocate a frozen allocation");
+
+        if occupied > 0 {
+            db::assert_token_released();
+        }
+
+        if !db::use_borrows(|borrows| borrows.is_empty()) {
+            for slot in self.slots() {
+                if let Some(gen) = NonZeroU64::new(slot.gen()) {
+                    assert!(
+                        db::use_borrows(|borrows| !borrows.contains_key(&gen)),
+                        "attempted to deallocate an allocation with borrowed objects",
+                    );
+                }
+            }
+        }
+
+        for child in children {
+            child.assert_deallocable();
+        }
+    }
+}
+
+/// Live values moved out of their slots, which are written back into the same slots on drop.
+///
+/// Callbacks such as `sort_by`'s comparator or `snapshot_with`'s cloner may reach the allocation
+/// through another handle, so they're handed detached values rather than references into the
+/// slots. While detached, the slots read as empty, so the callback can't take or overwrite a value
+/// it's still looking at. Anything put into a detached slot in the meantime is displaced when the
+/// detached value is written back.
+struct Detached<T: 'static> {
+    alloc: Allocation<T>,
+    indices: Vec<usize>,
+    values: Vec<(NonZeroU64, T)>,
+}
+
+impl<T> Detached<T> {
+    #[track_caller]
+    fn new(alloc: Allocation<T>, indices: impl IntoIterator<Item = usize>) -> Self {
+        let indices = indices
+            .into_iter()
+            .filter(|&index| alloc.slot(index).is_full())
+            .collect::<Vec<_>>();
+
+        // Every slot is checked before any is detached so that a panic can't strand the values
+        // detached so far.
+        for &index in &indices {
+            alloc.slot(index).assert_unborrowed();
+        }
+
+        let values = indices
+            .iter()
+            .map(|&index| unsafe { alloc.slot(index).detach() }.unwrap())
+            .collect();
+
+        Self {
+            alloc,
+            indices,
+            values,
+        }
+    }
+}
+
+impl<T> Drop for Detached<T> {
+    fn drop(&mut self) {
+        let displaced = db::use_object_db(|db| {
+            self.indices
+                .iter()
+                .zip(self.values.drain(..))
+                .filter_map(|(&index, (gen, value))| unsafe {
+                    self.alloc.slot(index).attach(db, gen, value)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        if !displaced.is_empty() {
+            self.alloc
+                .with_meta(|meta| meta.occupied -= displaced.len());
+        }
+    }
+}
+
+#[derive_where(Debug)]
+pub struct SnapshotMap<T: 'static> {
+    from: Allocation<T>,
+    to: Allocation<T>,
+    gens: Vec<Option<(NonZeroU64, NonZeroU64)>>,
+}
+
+impl<T> SnapshotMap<T> {
+    #[track_caller]
+    pub fn map(&self, old: Ref<T>) -> Option<Ref<T>> {
+        self.from.debug_assert_owns(old);
+
+        // The source values are detached while snapshotting so we match on generations rather
+        // than on liveness.
+        let index = self.from.slot_index(old.gen_ptr().as_ptr())?;
+        let (old_gen, new_gen) = self.gens[index]?;
+
+        (old.gen() == old_gen).then(|| self.to.ref_for(index, new_gen))
+    }
+}
+
+/// Takes every value out of an allocation, even those left over when the iterator is dropped.
+#[derive_where(Debug)]
+pub struct Drain<T: 'static> {
+    alloc: Allocation<T>,
+    index: usize,
+}
+
+impl<T> Iterator for Drain<T> {
+    type Item = (usize, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.alloc.len() {
+            let index = self.index;
+            self.index += 1;
+
+            if let Some(value) = self.alloc.take(index) {
+                return Some((index, value));
+            }
+        }
+
+        None
+    }
+}
+
+impl<T> Drop for Drain<T> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+/// Owns an allocation and deallocates it on drop.
+///
+/// This deliberately doesn't deref to [`Allocation`] so that the allocation can't be deallocated
+/// twice by accident. Use [`OwnedAllocation::handle`] to access its slots.
+#[derive_where(Debug)]
+pub struct OwnedAllocation<T: 'static> {
+    alloc: Allocation<T>,
+}
+
+impl<T> OwnedAllocation<T> {
+    pub fn new(len: usize) -> Self {
+        Self::from_raw(Allocation::new(len))
+    }
+
+    pub fn from_raw(alloc: Allocation<T>) -> Self {
+        Self { alloc }
+    }
+
+    pub fn handle(&self) -> Allocation<T> {
+        self.alloc
+    }
+
+    pub fn into_raw(self) -> Allocation<T> {
+        ManuallyDrop::new(self).alloc
+    }
+}
+
+impl<T> Drop for OwnedAllocation<T> {
+    fn drop(&mut self) {
+        self.alloc.dealloc();
+    }
+}
+
+/// A shared view of an allocation obtained through [`Allocation::freeze`].
+///
+/// Every object alive when the view was created is borrowed for as long as it exists, so none of
+/// them can be taken, moved, or overwritten, and nothing can be put into the allocation. In return,
+/// reads hand out plain references tied to the view without tracking any further borrows.
+///
+/// The view can't rule out mutation statically. [`Allocation`] handles are `Copy` and any [`Ref`]
+/// can write to or destroy its object, so borrowing one handle says nothing about what the others
+/// do. Freezing instead pays one borrow table entry per live object up front, which every path
+/// that can move, overwrite, or mutably borrow an object checks in every build, and which keeps
+/// the [`Token`] from being acquired. Reads through the view are free after that.
+pub struct FrozenAllocation<T: 'static> {
+    alloc: Allocation<T>,
+    gens: Vec<NonZeroU64>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> FrozenAllocation<T> {
+    pub fn handle(&self) -> Allocation<T> {
+        self.alloc
+    }
+
+    /// Returns the value `value` points to if it's alive and belongs to this allocation.
+    pub fn read(&self, value: Ref<T>) -> Option<&T> {
+        let index = self.alloc.index_of(value)?;
+        let slot = self.alloc.slot(index);
+
+        (slot.value_ptr() == value.value.as_ptr()).then(|| unsafe { &*slot.value_ptr() })
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let slot = self.alloc.try_slot(index)?;
+        slot.is_full().then(|| unsafe { &*slot.value_ptr() })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> + '_ {
+        (0..self.alloc.len()).filter_map(|index| Some((index, self.get(index)?)))
+    }
+}
+
+impl<T> fmt::Debug for FrozenAllocation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrozenAllocation")
+            .field("alloc", &self.alloc)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for FrozenAllocation<T> {
+    fn drop(&mut self) {
+        for &gen in &self.gens {
+            db::release_shared_borrow(gen);
+        }
+
+        self.alloc.with_meta(|meta| meta.frozen -= 1);
+    }
+}
+
+/// A borrowed view of an allocation obtained through [`Allocation::view`].
+///
+/// The references it hands out borrow the view, so they can't be stashed away past the end of an
+/// iteration and slots can only be emptied through the view once they're gone. Objects can still
+/// be taken through other handles to the allocation, so accesses through [`ViewRef`]s stay
+/// checked.
+pub struct View<'a, T: 'static> {
+    alloc: Allocation<T>,
+    _borrow: PhantomData<&'a mut Allocation<T>>,
+}
+
+impl<T> View<'_, T> {
+    pub fn len(&self) -> usize {
+        self.alloc.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alloc.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<ViewRef<'_, T>> {
+        let raw = self.alloc.try_get(index)?;
+        Some(ViewRef {
+            index,
+            raw,
+            _view: PhantomData,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ViewRef<'_, T>> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    #[track_caller]
+    pub fn put(&mut self, index: usize, value: T) -> ViewRef<'_, T> {
+        ViewRef {
+            index,
+            raw: self.alloc.put(index, value),
+            _view: PhantomData,
+        }
+    }
+
+    #[track_caller]
+    pub fn take(&mut self, index: usize) -> Option<T> {
+        self.alloc.take(index)
+    }
+}
+
+impl<T> fmt::Debug for View<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("View").field("alloc", &self.alloc).finish()
+    }
+}
+
+/// A reference handed out by a [`View`] which can't outlive it.
+#[derive_where(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ViewRef<'v, T: 'static> {
+    index: usize,
+    raw: Ref<T>,
+    _view: PhantomData<&'v ()>,
+}
+
+impl<T> ViewRef<'_, T> {
+    pub fn index(self) -> usize {
+        self.index
+    }
+
+    pub fn is_alive(self) -> bool {
+        self.raw.is_alive()
+    }
+
+    pub fn try_read(self) -> Option<T>
+    where
+        T: Copy,
+    {
+        self.raw.try_read()
+    }
+
+    #[track_caller]
+    pub fn read(self) -> T
+    where
+        T: Copy,
+    {
+        self.raw.read()
+    }
+
+    #[track_caller]
+    pub fn try_with<R>(self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.raw.try_with(f)
+    }
+
+    #[track_caller]
+    pub fn with<R>(self, f: impl FnOnce(&T) -> R) -> R {
+        self.raw.with(f)
+    }
+
+    #[track_caller]
+    pub fn try_update<R>(self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.raw.try_update(f)
+    }
+
+    #[track_caller]
+    pub fn update<R>(self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.raw.update(f)
+    }
+}
+
+/// A type-erased [`Allocation`] handle tagged with its value type.
+#[derive(Copy, Clone)]
+pub struct AnyAllocation {
+    type_id: TypeId,
+    header: NonNull<u8>,
+    incarnation: u64,
+    vtable: &'static AnyAllocationVtable,
+}
+
+struct AnyAllocationVtable {
+    type_name: fn() -> &'static str,
+    len: fn(AnyAllocation) -> usize,
+    is_alive: fn(AnyAllocation) -> bool,
+    dealloc: fn(AnyAllocation),
+    assert_deallocable: fn(AnyAllocation),
+}
+
+impl AnyAllocationVtable {
+    fn of<T: 'static>() -> &'static Self {
+        struct Holder<T>(T);
+
+        impl<T: 'static> Holder<T> {
+            const VTABLE: AnyAllocationVtable = AnyAllocationVtable {
+                type_name: std::any::type_name::<T>,
+                len: |alloc| alloc.unerase::<T>().len(),
+                is_alive: |alloc| alloc.unerase::<T>().is_alive(),
+                dealloc: |alloc| alloc.unerase::<T>().dealloc(),
+                assert_deallocable: |alloc| alloc.unerase::<T>().assert_deallocable(),
+            };
+        }
+
+        &Holder::<T>::VTABLE
+    }
+}
+
+impl AnyAllocation {
+    pub fn new<T: 'static>(alloc: Allocation<T>) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            header: alloc.header.cast(),
+            incarnation: alloc.incarnation,
+            vtable: AnyAllocationVtable::of::<T>(),
+        }
+    }
+
+    fn unerase<T: 'static>(self) -> Allocation<T> {
+        self.downcast().unwrap()
+    }
+
+    pub fn type_id(self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn is<T: 'static>(self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    pub fn downcast<T: 'static>(self) -> Option<Allocation<T>> {
+        self.is::<T>().then(|| Allocation {
+            header: self.header.cast(),
+            incarnation: self.incarnation,
+        })
+    }
+
+    pub fn len(self) -> usize {
+        (self.vtable.len)(self)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_alive(self) -> bool {
+        (self.vtable.is_alive)(self)
+    }
+
+    pub fn dealloc(self) {
+        (self.vtable.dealloc)(self)
+    }
+
+    fn assert_deallocable(self) {
+        (self.vtable.assert_deallocable)(self)
+    }
+}
+
+impl fmt::Debug for AnyAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyAllocation")
+            .field("type", &(self.vtable.type_name)())
+            .field("header", &self.header)
+            .field("incarnation", &self.incarnation)
+            .finish()
+    }
+}
+
+impl<T: 'static> From<Allocation<T>> for AnyAllocation {
+    fn from(alloc: Allocation<T>) -> Self {
+        Self::new(alloc)
+    }
+}
+
+/// What an [`Arena`] does when a value is pushed while every slot is occupied.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// Grow the arena to make room.
+    #[default]
+    Grow,
+    /// Reject the value. Capacity only changes through [`Arena::reserve`].
+    Error,
+    /// Drop the longest-lived value and put the new one in its place, like a ring buffer.
+    ReplaceOldest,
+}
+
+#[derive_where(Debug)]
+pub struct Arena<T: 'static> {
+    alloc: Allocation<T>,
+    policy: FullPolicy,
+    // Values in push order, only tracked under `FullPolicy::ReplaceOldest`. Values removed in the
+    // meantime are skipped over lazily.
+    order: VecDeque<Ref<T>>,
+}
+
+impl<T> Arena<T> {
+    const INITIAL_LEN: usize = 16;
+
+    pub fn new() -> Self {
+        Self::with_policy(FullPolicy::Grow)
+    }
+
+    pub fn with_policy(policy: FullPolicy) -> Self {
+        Self::with_capacity_and_policy(0, policy)
+    }
+
+    /// Creates an arena with room for `capacity` values before `policy` is consulted, such as a
+    /// ring buffer of a given size under [`FullPolicy::ReplaceOldest`].
+    pub fn with_capacity_and_policy(capacity: usize, policy: FullPolicy) -> Self {
+        Self {
+            alloc: Allocation::new(capacity),
+            policy,
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn policy(&self) -> FullPolicy {
+        self.policy
+    }
+
+    pub fn push(&mut self, value: T) -> Ref<T> {
+        self.try_push(value).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_push(&mut self, value: T) -> Result<Ref<T>, PutError<T>> {
+        if self.alloc.remaining_capacity() == 0 {
+            match self.policy {
+                FullPolicy::Grow => {
+                    // Growing only appends pages so existing `Ref`s stay valid.
+                    self.alloc
+                        .grow((self.alloc.len() * 2).max(Self::INITIAL_LEN));
+                }
+                FullPolicy::Error => {
+                    return Err(PutError {
+                        kind: PutErrorKind::Full,
+                        value,
+                    });
+                }
+                FullPolicy::ReplaceOldest if self.alloc.is_empty() => {
+                    // There's nothing to replace yet, so the ring gets its initial capacity.
+                    self.alloc.grow(Self::INITIAL_LEN);
+                }
+                FullPolicy::ReplaceOldest => {
+                    let Some(index) = self.pop_oldest() else {
+                        return Err(PutError {
+                            kind: PutErrorKind::Full,
+                            value,
+                        });
+                    };
+
+                    let replaced = self.alloc.put(index, value);
+                    self.order.push_back(replaced);
+                    return Ok(replaced);
+                }
+            }
+        }
+
+        let inserted = self.alloc.insert(value).unwrap();
+
+        if self.policy == FullPolicy::ReplaceOldest {
+            self.order.push_back(inserted);
+
+            // Keep removed values from piling up in the queue.
+            if self.order.len() > self.capacity() * 2 {
+                self.order.retain(|value| value.is_alive());
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    fn pop_oldest(&mut self) -> Option<usize> {
+        while let Some(oldest) = self.order.pop_front() {
+            if let Some(index) = self.alloc.index_of(oldest) {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.alloc.len()
+    }
+
+    pub fn len_alive(&self) -> usize {
+        self.capacity() - self.remaining_capacity()
+    }
+
+    pub fn remaining_capacity(&self) -> usize {
+        self.alloc.remaining_capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let remaining = self.remaining_capacity();
+
+        if remaining < additional {
+            self.alloc.grow(self.alloc.len() + additional - remaining);
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.alloc.shrink_to_fit();
+    }
+
+    #[track_caller]
+    pub fn remove(&mut self, value: Ref<T>) -> Option<T> {
+        self.alloc.take_ref(value)
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FromIterator<T> for Arena<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut arena = Self::new();
+        arena.extend(iter);
+        arena
+    }
+}
+
+impl<T> Extend<T> for Arena<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        self.alloc.dealloc();
+    }
+}
+
+#[derive_where(Debug)]
+pub struct ReclaimingAllocation<T: 'static> {
+    alloc: Option<Allocation<T>>,
+}
+
+impl<T> ReclaimingAllocation<T> {
+    pub fn allocation(&self) -> Option<Allocation<T>> {
+        self.alloc
+    }
+
+    pub fn is_reclaimed(&self) -> bool {
+        self.alloc.is_none()
+    }
+
+    pub fn put(&mut self, index: usize, value: T) -> Option<Ref<T>> {
+        Some(self.alloc?.put(index, value))
+    }
+
+    pub fn take(&mut self, index: usize) -> Option<T> {
+        let taken = self.alloc?.take(index)?;
+        self.reclaim_if_empty();
+        Some(taken)
+    }
+
+    pub fn retain(&mut self, pred: impl FnMut(usize, Ref<T>) -> bool) {
+        if let Some(alloc) = self.alloc {
+            alloc.retain(pred);
+            self.reclaim_if_empty();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(alloc) = self.alloc {
+            alloc.clear();
+            self.reclaim_if_empty();
+        }
+    }
+
+    fn reclaim_if_empty(&mut self) {
+        // Occupancy is tracked by the allocation itself so this also notices slots emptied
+        // through the raw handle.
+        if let Some(alloc) = self
+            .alloc
+            .filter(|alloc| alloc.remaining_capacity() == alloc.len())
+        {
+            alloc.dealloc();
+            self.alloc = None;
+        }
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+#[repr(transparent)]
+struct GenCell(Cell<u64>);
+
+#[cfg(feature = "sync")]
+#[repr(transparent)]
+struct GenCell(std::sync::atomic::AtomicU64);
+
+impl GenCell {
+    #[cfg(not(feature = "sync"))]
+    fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    #[cfg(feature = "sync")]
+    fn get(&self) -> u64 {
+        self.0.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    #[cfg(not(feature = "sync"))]
+    fn set(&self, gen: u64) {
+        self.0.set(gen)
+    }
+
+    #[cfg(feature = "sync")]
+    fn set(&self, gen: u64) {
+        self.0.store(gen, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn as_ptr(&self) -> *mut u64 {
+        self.0.as_ptr()
+    }
+
+    #[inline(always)]
+    #[cfg(not(feature = "sync"))]
+    unsafe fn read_ptr(ptr: *mut u64) -> u64 {
+        *ptr
+    }
+
+    #[inline(always)]
+    #[cfg(feature = "sync")]
+    unsafe fn read_ptr(ptr: *mut u64) -> u64 {
+        std::sync::atomic::AtomicU64::from_ptr(ptr).load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// A single slot of an [`Allocation`].
+///
+/// Slots have a stable layout so that they can be handed to foreign code: the generation is a
+/// `u64` at offset 0, where zero marks an empty slot, and the value is stored at the first offset
+/// after it which is aligned for `T`. Slots are stored back to back with a stride of
+/// `size_of::<Generational<T>>()`.
+#[repr(C)]
+pub struct Generational<T> {
+    gen: GenCell,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Generational<T> {
+    pub(crate) fn is_full(&self) -> bool {
+        self.gen.get() != 0
+    }
+
+    pub(crate) fn gen_ptr(&self) -> *mut u64 {
+        self.gen.as_ptr()
+    }
+
+    pub(crate) fn gen(&self) -> u64 {
+        self.gen.get()
+    }
+
+    pub(crate) fn value_ptr(&self) -> *mut T {
+        // `Generational` is `repr(C)` so the value field is always aligned for `T`, even for
+        // over-aligned types.
+        let ptr = self.value.get().cast::<T>();
+        debug_assert!(ptr.is_aligned());
+        ptr
+    }
+
+    /// Moves the value out of the slot, wiping the bytes left behind if `zeroize` is enabled.
+    pub(crate) unsafe fn read_out(&self) -> T {
+        let value = self.value_ptr().read();
+
+        #[cfg(feature = "zeroize")]
+        {
+            // Volatile writes so the wipe isn't optimized out as a dead store.
+            let bytes = self.value_ptr().cast::<u8>();
+            for offset in 0..size_of::<T>() {
+                bytes.add(offset).write_volatile(0);
+            }
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+        }
+
+        value
+    }
+
+    fn assert_unborrowed(&self) {
+        if let Some(gen) = NonZeroU64::new(self.gen()) {
+            db::assert_token_released();
+            db::assert_unborrowed(gen);
+        }
+    }
+
+    pub(crate) fn make_ref(&self, gen: NonZeroU64) -> Ref<T> {
+        Ref::new_raw(
+            NonNull::from(&self.gen).cast(),
+            gen,
+            NonNull::from(&self.value).cast(),
+        )
+    }
+
+    pub(crate) fn rebrand(&self, gen: NonZeroU64) {
+        debug_assert!(self.is_full());
+        self.assert_unborrowed();
+
+        let old_gen = NonZeroU64::new(self.gen()).unwrap();
+        db::use_destroy_hooks(|hooks| {
+            if let Some(moved) = hooks.remove(&old_gen) {
+                hooks.insert(gen, moved);
+            }
+        });
+
+        db::use_object_db(|db| {
+            db.remove(&old_gen);
+
+            match db.entry(gen) {
+                hashbrown::hash_map::Entry::Occupied(_) => panic!("Reused generation {gen:?}"),
+                hashbrown::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(self.gen_ptr());
+                }
+            }
+        });
+
+        self.gen.set(gen.get());
+    }
+
+    pub(crate) unsafe fn move_into(&self, target: &Self) {
+        debug_assert!(!target.is_full());
+
+        if !self.is_full() {
+            return;
+        }
+
+        self.assert_unborrowed();
+
+        let gen = NonZeroU64::new(self.gen()).unwrap();
+        db::assert_unpinned(gen);
+        db::use_object_db(|db| db.insert(gen, target.gen_ptr()));
+
+        target.value_ptr().write(self.read_out());
+        target.gen.set(gen.get());
+        self.gen.set(0);
+    }
+
+    pub(crate) fn swap_with(&self, other: &Self) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+
+        self.assert_unborrowed();
+        other.assert_unborrowed();
+
+        for gen in [self.gen(), other.gen()] {
+            if let Some(gen) = NonZeroU64::new(gen) {
+                db::assert_unpinned(gen);
+            }
+        }
+
+        unsafe { std::ptr::swap(self.value.get(), other.value.get()) };
+
+        let (self_gen, other_gen) = (self.gen(), other.gen());
+        self.gen.set(other_gen);
+        other.gen.set(self_gen);
+
+        // Point the object DB at the new locations so `MovableRef`s can find them again.
+        db::use_object_db(|db| {
+            if let Some(gen) = NonZeroU64::new(self_gen) {
+                db.insert(gen, other.gen_ptr());
+            }
+
+            if let Some(gen) = NonZeroU64::new(other_gen) {
+                db.insert(gen, self.gen_ptr());
+            }
+        });
+    }
+
+    /// Moves the value out of the slot without releasing its generation from the object DB.
+    pub(crate) unsafe fn detach(&self) -> Option<(NonZeroU64, T)> {
+        let gen = NonZeroU64::new(self.gen())?;
+        db::assert_token_released();
+        db::assert_unborrowed(gen);
+        self.gen.set(0);
+
+        Some((gen, self.read_out()))
+    }
+
+    /// Moves a detached value back into the slot, returning whatever was put there in the
+    /// meantime.
+    pub(crate) unsafe fn attach(
+        &self,
+        db: &mut NopHashMap<NonZeroU64, *mut u64>,
+        gen: NonZeroU64,
+        value: T,
+    ) -> Option<T> {
+        self.assert_unborrowed();
+
+        let displaced = self.is_full().then(|| {
+            db.remove(&NonZeroU64::new(self.gen()).unwrap());
+            self.read_out()
+        });
+
+        db.insert(gen, self.gen_ptr());
+        self.value_ptr().write(value);
+        self.gen.set(gen.get());
+
+        displaced
+    }
+
+    pub(crate) unsafe fn disconnect(&self, db: &mut NopHashMap<NonZeroU64, *mut u64>) -> Option<T> {
+        if !self.is_full() {
+            return None;
+        }
+
+        self.assert_unborrowed();
+        db.remove(&NonZeroU64::new(self.gen()).unwrap());
+        self.gen.set(0);
+
+        Some(self.read_out())
+    }
+
+    pub(crate) unsafe fn replace(&self, value: Option<(NonZeroU64, T)>) -> Option<T> {
+        db::use_object_db(|db| self.replace_in(db, value))
+    }
+
+    pub(crate) unsafe fn replace_in(
+        &self,
+        db: &mut NopHashMap<NonZeroU64, *mut u64>,
+        value: Option<(NonZeroU64, T)>,
+    ) -> Option<T> {
+        self.assert_unborrowed();
+
+        let old = if self.is_full() {
+            db.remove(&NonZeroU64::new(self.gen()).unwrap());
+
+            Some(unsafe { self.read_out() })
+        } else {
+            None
+        };
+
+        if let Some((gen, value)) = value {
+            // Replace entry in Object DB
+            match db.entry(gen) {
+                hashbrown::hash_map::Entry::Occupied(_) => panic!("Reused generation {gen:?}"),
+                hashbrown::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(self.gen_ptr());
+                }
+            }
+
+            self.gen.set(gen.get());
+            self.value_ptr().write(value);
+        } else {
+            self.gen.set(0);
+        }
+
+        old
+    }
+}
+
+impl<T> Drop for Generational<T> {
+    fn drop(&mut self) {
+        if self.is_full() {
+            unsafe { self.value_ptr().drop_in_place() };
+        }
+    }
+}
+
+// === SliceArena === //
+
+/// An arena of variable-length slices, each handed out as a [`Ref<[T]>`] which carries its length.
+///
+/// Every slice lives in its own block holding a generation followed by its elements, so element
+/// and [`subslice`](Ref::subslice) projections check liveness like any other reference. Blocks
+/// come in power-of-two capacity classes and, like pages, are pooled per type rather than freed so
+/// stale references can keep reading their generation. Slices aren't registered in the object DB
+/// so they can't be found by generation.
+#[derive_where(Debug)]
+pub struct SliceArena<T: 'static> {
+    // The block and length of every live slice.
+    live: NopHashMap<NonZeroU64, (NonNull<u64>, usize)>,
+    gens: Cell<(u64, u64)>,
+    _ty: PhantomData<T>,
+}
+
+impl<T> SliceArena<T> {
+    pub fn new() -> Self {
+        Self {
+            live: NopHashMap::default(),
+            gens: Cell::new((0, 0)),
+            _ty: PhantomData,
+        }
+    }
+
+    fn class_for(len: usize) -> u32 {
+        len.next_power_of_two().trailing_zeros()
+    }
+
+    /// The layout of a block in capacity class `class` and the offset of its elements.
+    fn block_layout(class: u32) -> (Layout, usize) {
+        Layout::array::<T>(1 << class)
+            .and_then(|elems| Layout::new::<u64>().extend(elems))
+            .expect("bees: allocation size overflow")
+    }
+
+    fn elems_ptr(block: NonNull<u64>, len: usize) -> NonNull<[T]> {
+        let (_, offset) = Self::block_layout(Self::class_for(len));
+        let elems = unsafe { block.cast::<u8>().add(offset).cast::<T>() };
+        NonNull::slice_from_raw_parts(elems, len)
+    }
+
+    /// The number of live slices.
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    /// Moves `values` into a new slice.
+    pub fn push(&mut self, values: impl IntoIterator<Item = T>) -> Ref<[T]> {
+        let mut values = values.into_iter().collect::<Vec<_>>();
+        let len = values.len();
+        let class = Self::class_for(len);
+
+        let block = db::use_free_pool::<T, _>(|pool| pool.slice_blocks.get_mut(&class)?.pop())
+            .unwrap_or_else(|| {
+                GlobalStorage
+                    .alloc_zeroed(Self::block_layout(class).0)
+                    .unwrap_or_else(|never| match never {})
+                    .cast()
+            });
+
+        let gen = db::reserve_gens(&self.gens, 1);
+        let value = Self::elems_ptr(block, len);
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(values.as_ptr(), value.as_ptr().cast::<T>(), len);
+            values.set_len(0);
+            block.cast::<GenCell>().as_ref().set(gen.get());
+        }
+
+        self.live.insert(gen, (block, len));
+
+        Ref::new_raw(block, gen, value)
+    }
+
+    /// Removes the slice `slice` covers, returning its elements, or `None` if it's dead or only
+    /// covers part of a slice.
+    #[track_caller]
+    pub fn take(&mut self, slice: Ref<[T]>) -> Option<Vec<T>> {
+        let &(block, len) = self.live.get(&slice.gen())?;
+
+        let covers_whole = block == slice.gen_ptr()
+            && slice.len() == len
+            && slice.value.cast::<T>() == Self::elems_ptr(block, len).cast::<T>();
+
+        if !covers_whole {
+            return None;
+        }
+
+        db::assert_token_released();
+        db::assert_unborrowed(slice.gen());
+
+        #[cfg(feature = "debug-tools")]
+        db::record_destruction(slice.gen(), std::panic::Location::caller());
+
+        self.live.remove(&slice.gen());
+        let values = unsafe { Self::release(block, len) };
+
+        // Hooks run once the slice is gone so they're free to use the arena's other slices.
+        let hooks = db::use_destroy_hooks(|hooks| hooks.remove(&slice.gen()));
+        for hook in hooks.into_iter().flatten() {
+            hook();
+        }
+
+        Some(values)
+    }
+
+    /// Kills the slice in `block` and moves its elements out, returning the block to the pool.
+    unsafe fn release(block: NonNull<u64>, len: usize) -> Vec<T> {
+        block.cast::<GenCell>().as_ref().set(0);
+
+        let mut values = Vec::with_capacity(len);
+        std::ptr::copy_nonoverlapping(
+            Self::elems_ptr(block, len).as_ptr().cast::<T>(),
+            values.as_mut_ptr(),
+            len,
+        );
+        values.set_len(len);
+
+        db::use_free_pool::<T, _>(|pool| {
+            pool.slice_blocks
+                .entry(Self::class_for(len))
+                .or_default()
+                .push(block)
+        });
+
+        values
+    }
+}
+
+impl<T> Default for SliceArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SliceArena<T> {
+    fn drop(&mut self) {
+        if self.live.is_empty() {
+            return;
+        }
+
+        // Nothing is torn down until we know every slice can be.
+        db::assert_token_released();
+        for &gen in self.live.keys() {
+            db::assert_unborrowed(gen);
+        }
+
+        // Elements are dropped once every slice is dead so their destructors can't observe a
+        // half-dropped arena.
+        let (gens, values): (Vec<_>, Vec<_>) = self
+            .live
+            .drain()
+            .map(|(gen, (block, len))| (gen, unsafe { Self::release(block, len) }))
+            .unzip();
+
+        drop(values);
+
+        for gen in gens {
+            let hooks = db::use_destroy_hooks(|hooks| hooks.remove(&gen));
+            for hook in hooks.into_iter().flatten() {
+                hook();
+            }
+        }
+    }
+}
+
+// === Ref === //
+
+const DANGLING_ERR: &str = "attempted to deref a dead pointer";
+
+// The generation `Ref::dangling` points at. It's never written so it always reads as empty.
+static DANGLING_GEN: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Refs store how far their value sits past its slot's generation in units of the generation's
+/// alignment, and round down to recover the rest.
+const REF_OFFSET_UNIT: usize = align_of::<u64>();
+
+const MAX_REF_OFFSET_UNITS: usize = (1 << (64 - db::GEN_BITS)) - 1;
+
+/// Refs compare and hash by identity: two refs are equal if they point to the same place in the same
+/// incarnation of an object.
+///
+/// A ref is two words wide for sized values. Its generation cell is found by stepping back from the
+/// value, so values can sit at most 512 KiB past the start of their slot, which only limits
+/// projections into very large objects and slices.
+#[derive_where(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ref<T: ?Sized + 'static> {
+    value: NonNull<T>,
+    // The generation in the low `db::GEN_BITS` bits and, above them, how far past its slot's
+    // generation cell the value sits. That's a fixed offset for whole objects, but refs projected
+    // through `subfield!`, `index`, or `subslice` point into the middle of one.
+    tag: NonZeroU64,
+}
+
+impl<T: ?Sized> Ref<T> {
+    /// Creates a reference which is never alive and never points to a real object.
+    ///
+    /// Its value pointer sits just past a generation cell which is never written, and must not be
+    /// dereferenced.
+    pub const fn dangling() -> Self
+    where
+        T: Sized,
+    {
+        let units = (size_of::<u64>() / REF_OFFSET_UNIT) as u64;
+
+        Self {
+            value: unsafe { NonNull::new_unchecked(DANGLING_GEN.as_ptr().wrapping_add(1).cast()) },
+            tag: unsafe {
+                NonZeroU64::new_unchecked(db::DANGLING_GEN.get() | units << db::GEN_BITS)
+            },
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    fn new_raw(gen_ptr: NonNull<u64>, gen: NonZeroU64, value: NonNull<T>) -> Self {
+        let units = value
+            .cast::<u8>()
+            .as_ptr()
+            .addr()
+            .wrapping_sub(gen_ptr.as_ptr().addr())
+            / REF_OFFSET_UNIT;
+
+        assert!(
+            units <= MAX_REF_OFFSET_UNITS,
+            "attempted to make a reference to a value more than 512 KiB past its generation",
+        );
+        debug_assert!(gen_ptr.as_ptr().is_aligned() && gen.get() <= db::MAX_GEN);
+
+        Self {
+            value,
+            tag: unsafe { NonZeroU64::new_unchecked(gen.get() | (units as u64) << db::GEN_BITS) },
+        }
+    }
+
+    /// The generation identifying this incarnation of the object. See [`Ref::find_by_gen`].
+    #[inline(always)]
+    pub fn gen(self) -> NonZeroU64 {
+        unsafe { NonZeroU64::new_unchecked(self.tag.get() & db::MAX_GEN) }
+    }
+
+    #[inline(always)]
+    fn gen_ptr(self) -> NonNull<u64> {
+        let units = (self.tag.get() >> db::GEN_BITS) as usize;
+        let gen_ptr = self
+            .value
+            .cast::<u8>()
+            .as_ptr()
+            .wrapping_sub(units * REF_OFFSET_UNIT)
+            .map_addr(|addr| addr & !(REF_OFFSET_UNIT - 1));
+
+        unsafe { NonNull::new_unchecked(gen_ptr.cast()) }
+    }
+
+    /// How many bytes past its slot's generation the value sits.
+    fn offset(self) -> usize {
+        self.value.cast::<u8>().as_ptr().addr() - self.gen_ptr().as_ptr().addr()
+    }
+
+    /// Decomposes the reference into a pointer to its slot's generation, the generation it expects
+    /// to find there, and a pointer to its value.
+    pub fn into_raw_parts(self) -> (NonNull<u64>, NonZeroU64, NonNull<T>) {
+        (self.gen_ptr(), self.gen(), self.value)
+    }
+
+    /// Reconstructs a reference from the parts returned by [`Ref::into_raw_parts`].
+    ///
+    /// Panics if `value` sits before `gen_ptr` or more than 512 KiB past it.
+    ///
+    /// # Safety
+    ///
+    /// `gen_ptr` must stay readable for the rest of the program and, whenever it holds `gen`,
+    /// `value` must point to a valid `T`. Parts obtained from `into_raw_parts` on a `Ref<T>` of the
+    /// same `T` always satisfy this, even once the object is gone.
+    #[track_caller]
+    pub unsafe fn from_raw_parts(
+        gen_ptr: NonNull<u64>,
+        gen: NonZeroU64,
+        value: NonNull<T>,
+    ) -> Self {
+        assert!(
+            gen.get() <= db::MAX_GEN,
+            "attempted to make a reference with a generation wider than 48 bits",
+        );
+
+        Self::new_raw(gen_ptr, gen, value)
+    }
+
+    pub fn id(self) -> ObjId {
+        ObjId(self.gen())
+    }
+
+    /// Checks whether both refs point to the same place in the same slot and incarnation.
+    ///
+    /// Unlike `==`, this ignores slice lengths and other pointer metadata.
+    pub fn ptr_eq(self, other: Self) -> bool {
+        self.tag == other.tag && self.value.cast::<u8>() == other.value.cast::<u8>()
+    }
+
+    /// Finds the live object of type `T` with the generation `gen`, if any.
+    ///
+    /// Objects can only be found on the thread which created them. Looking up a generation recently
+    /// issued on another thread panics rather than reporting the object as dead.
+    #[track_caller]
+    pub fn find_by_gen(gen: NonZeroU64) -> Option<Self>
+    where
+        T: Sized,
+    {
+        let Some(gen_ptr) = db::use_object_db(|db| db.get(&gen).copied()) else {
+            db::assert_local_gen(gen);
+            return None;
+        };
+
+        // The page registry tells us whether the slot actually holds a `T`.
+        let alloc = Allocation::<T>::from_header(db::find_header(gen_ptr)?);
+        let slot = alloc.slot(alloc.slot_index(gen_ptr)?);
+
+        (slot.gen() == gen.get()).then(|| slot.make_ref(gen))
+    }
+
+    pub fn to_movable(self) -> MovableRef<T>
+    where
+        T: Sized,
+    {
+        MovableRef::new(self)
+    }
+
+    /// Pins the object in its slot, returning `None` if it's dead.
+    pub fn try_pin(self) -> Option<PinnedRef<T>>
+    where
+        T: Sized,
+    {
+        if !self.is_alive() {
+            return None;
+        }
+
+        db::use_pins(|pins| *pins.entry(self.gen()).or_insert(0) += 1);
+        Some(PinnedRef { raw: self })
+    }
+
+    #[track_caller]
+    pub fn pin(self) -> PinnedRef<T>
+    where
+        T: Sized,
+    {
+        match self.try_pin() {
+            Some(pinned) => pinned,
+            None => self.panic_dangling(),
+        }
+    }
+
+    #[track_caller]
+    pub fn to_thin(self) -> ThinRef<T>
+    where
+        T: Sized,
+    {
+        ThinRef::new(self)
+    }
+
+    /// Spawns `value` into the global arena for `T`. See [`spawn`].
+    pub fn new(value: T) -> Self
+    where
+        T: Sized,
+    {
+        spawn(value)
+    }
+
+    /// Takes the value out of its slot, killing every reference to it.
+    ///
+    /// This works for values in any allocation, including those created with [`Ref::new`].
+    #[track_caller]
+    pub fn destroy(self) -> T
+    where
+        T: Sized,
+    {
+        self.try_destroy()
+            .expect("attempted to destroy a dead object")
+    }
+
+    #[track_caller]
+    pub fn try_destroy(self) -> Option<T>
+    where
+        T: Sized,
+    {
+        self.allocation()?.take_ref(self)
+    }
+
+    /// Registers `hook` to run once the object is destroyed.
+    #[track_caller]
+    pub fn on_destroy(self, hook: impl FnOnce() + 'static) {
+        if !self.is_alive() {
+            self.panic_dangling();
+        }
+
+        db::use_destroy_hooks(|hooks| hooks.entry(self.gen()).or_default().push(Box::new(hook)));
+    }
+
+    /// Finds the allocation holding the referenced value, if it's still alive.
+    pub fn allocation(self) -> Option<Allocation<T>>
+    where
+        T: Sized,
+    {
+        if !self.is_alive() {
+            return None;
+        }
+
+        // Live slots always sit in a registered page of a live allocation.
+        let alloc = Allocation::from_header(db::find_header(self.gen_ptr().as_ptr())?);
+        let index = alloc.slot_index(self.gen_ptr().as_ptr())?;
+
+        (alloc.slot(index).value_ptr() == self.value.as_ptr()).then_some(alloc)
+    }
+
+    /// Follows the object to the slot it was moved to by [`Allocation::compact`] and friends.
+    /// References to objects which are still in place or which were destroyed are returned as-is.
+    #[track_caller]
+    pub fn relocated(self) -> Self
+    where
+        T: Sized,
+    {
+        if self.is_alive() || self.gen_ptr().as_ptr() == DANGLING_GEN.as_ptr() {
+            return self;
+        }
+
+        // The object DB tracks where every live generation currently sits.
+        let Some(gen_ptr) = db::use_object_db(|db| db.get(&self.gen()).copied()) else {
+            db::assert_local_gen(self.gen());
+            return self;
+        };
+
+        // Objects only move between slots of the same type, so the value keeps its offset from
+        // the generation even if it was a projection.
+        let gen_ptr = NonNull::new(gen_ptr).unwrap();
+
+        Self {
+            value: unsafe { gen_ptr.cast::<u8>().add(self.offset()).cast::<T>() },
+            tag: self.tag,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_alive(self) -> bool {
+        self.gen().get() == unsafe { GenCell::read_ptr(self.gen_ptr().as_ptr()) }
+    }
+
+    #[cold]
+    #[track_caller]
+    fn panic_dangling(self) -> ! {
+        #[cfg(feature = "debug-tools")]
+        {
+            let site = db::destruction_site(self.gen())
+                .map_or(String::new(), |site| format!(", destroyed at {site}"));
+
+            panic!(
+                "{DANGLING_ERR} (type `{}`, generation {}, slot now holds generation {}{site})",
+                std::any::type_name::<T>(),
+                self.gen(),
+                unsafe { GenCell::read_ptr(self.gen_ptr().as_ptr()) },
+            );
+        }
+
+        #[cfg(not(feature = "debug-tools"))]
+        panic!("{DANGLING_ERR}");
+    }
+
+    /// Checks whether every reference in `refs` is alive.
+    ///
+    /// Every generation is loaded without early exits so the loads can overlap.
+    pub fn all_alive(refs: &[Self]) -> bool {
+        refs.iter().fold(true, |all, r| all & r.is_alive())
+    }
+
+    /// Writes the liveness of each reference in `refs` into a bitmask, one bit per reference in
+    /// little-endian order.
+    pub fn alive_mask(refs: &[Self]) -> Vec<u64> {
+        refs.chunks(64)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |mask, (i, r)| mask | (r.is_alive() as u64) << i)
+            })
+            .collect()
+    }
+
+    /// Splits `refs` into the alive and the dead references, preserving their order.
+    pub fn partition_alive(refs: &[Self]) -> (Vec<Self>, Vec<Self>) {
+        refs.iter().partition(|r| r.is_alive())
+    }
+
+    #[inline(always)]
+    pub fn get_unchecked(self) -> *mut T {
+        self.value.as_ptr()
+    }
+
+    /// Returns the value pointer without checking liveness.
+    ///
+    /// The pointer keeps the provenance of the allocation's page, so it can be offset and cast
+    /// with the strict-provenance APIs.
+    #[inline(always)]
+    pub fn as_non_null(self) -> NonNull<T> {
+        self.value
+    }
+
+    #[inline(always)]
+    pub fn try_get(self) -> Option<*mut T> {
+        if self.is_alive() {
+            Some(self.get_unchecked())
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn get(self) -> *mut T {
+        match self.try_get() {
+            Some(ptr) => ptr,
+            None => self.panic_dangling(),
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_read(self) -> Option<T>
+    where
+        T: Copy,
+    {
+        let ptr = self.try_get()?;
+
+        // The borrow table is empty unless guards are live, so this check stays cheap in the
+        // common case.
+        db::assert_token_released();
+        db::assert_not_borrowed_mut(self.gen());
+
+        Some(unsafe { ptr.read() })
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn read(self) -> T
+    where
+        T: Copy,
+    {
+        match self.try_read() {
+            Some(value) => value,
+            None => self.panic_dangling(),
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_write(self, value: T) -> Option<T>
+    where
+        T: Sized,
+    {
+        if let Some(ptr) = self.try_get() {
+            db::assert_token_released();
+            db::assert_unborrowed(self.gen());
+
+            let read = unsafe { ptr.read() };
+            unsafe { ptr.write(value) };
+            Some(read)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn write(self, value: T) -> T
+    where
+        T: Sized,
+    {
+        match self.try_write(value) {
+            Some(old) => old,
+            None => self.panic_dangling(),
+        }
+    }
+
+    /// Like [`Ref::try_write`], under the name `std::mem::replace` uses.
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_replace(self, value: T) -> Option<T>
+    where
+        T: Sized,
+    {
+        self.try_write(value)
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn replace(self, value: T) -> T
+    where
+        T: Sized,
+    {
+        self.write(value)
+    }
+
+    /// Exchanges the values of two objects, returning `false` without touching either if one of
+    /// them is dead.
+    #[track_caller]
+    pub fn try_swap(self, other: Ref<T>) -> bool
+    where
+        T: Sized,
+    {
+        let (Some(a), Some(b)) = (self.try_get(), other.try_get()) else {
+            return false;
+        };
+
+        db::assert_token_released();
+        db::assert_unborrowed(self.gen());
+        db::assert_unborrowed(other.gen());
+
+        unsafe { std::ptr::swap(a, b) };
+        true
+    }
+
+    #[track_caller]
+    pub fn swap(self, other: Ref<T>)
+    where
+        T: Sized,
+    {
+        if !self.try_swap(other) {
+            if self.is_alive() {
+                other.panic_dangling()
+            } else {
+                self.panic_dangling()
+            }
+        }
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_set(self, value: T) -> bool
+    where
+        T: Sized,
+    {
+        if let Some(ptr) = self.try_get() {
+            db::assert_token_released();
+            db::assert_unborrowed(self.gen());
+
+            // The new value goes in before the old one is dropped so that a panicking destructor
+            // can't leave a dropped value behind in a live slot.
+            drop(unsafe { ptr.replace(value) });
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Creates a reference to `data` which lives exactly as long as this reference.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point into the value this reference points to, such as to one of its fields.
+    #[inline(always)]
+    #[track_caller]
+    pub unsafe fn subfield_unchecked<U: ?Sized>(self, data: *mut U) -> Ref<U> {
+        Ref::new_raw(self.gen_ptr(), self.gen(), NonNull::new_unchecked(data))
+    }
+
+    /// Converts the pointer to the referenced value with `f`, keeping the same liveness.
+    ///
+    /// Prefer the [`unsize!`] macro, which only accepts unsizing coercions such as `T` to
+    /// `dyn Trait`.
+    ///
+    /// # Safety
+    ///
+    /// `f` must return a pointer to the same value, e.g. by unsizing it.
+    #[inline(always)]
+    pub unsafe fn unsize_unchecked<U: ?Sized>(self, f: impl FnOnce(*mut T) -> *mut U) -> Ref<U> {
+        self.subfield_unchecked(f(self.get_unchecked()))
+    }
+
+    /// Reinterprets the referenced slot as a `U`, returning `None` if `U`'s layout is incompatible
+    /// with `T`'s.
+    ///
+    /// # Safety
+    ///
+    /// The bit pattern of the current and any future `T` must be valid for `U` (and vice versa if
+    /// written through).
+    #[inline(always)]
+    pub unsafe fn try_reinterpret<U>(self) -> Option<Ref<U>>
+    where
+        T: Sized,
+    {
+        if size_of::<T>() == size_of::<U>() && align_of::<U>() <= align_of::<T>() {
+            Some(self.subfield_unchecked(self.value.cast::<U>().as_ptr()))
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_for_macro(self, _: FuncDisambiguator) -> (Self, *mut T) {
+        (self, self.get())
+    }
+
+    #[inline(always)]
+    pub fn try_get_for_macro(self, _: FuncDisambiguator) -> Option<(Self, *mut T)> {
+        self.try_get().map(|ptr| (self, ptr))
+    }
+
+    pub fn wrap(self) -> T::Wrapper
+    where
+        T: Struct,
+    {
+        RefWrapper::from_raw(self)
+    }
+
+    /// Presents this reference as the wrapper of a layout-compatible type, e.g. a
+    /// `#[repr(transparent)]` newtype over `T`.
+    ///
+    /// # Safety
+    ///
+    /// The bit pattern of the current and any future `T` must be valid for `W::Pointee` (and vice
+    /// versa if written through).
+    pub unsafe fn cast_wrapper<W: RefWrapper>(self) -> W
+    where
+        T: Sized,
+    {
+        assert!(
+            size_of::<T>() == size_of::<W::Pointee>()
+                && align_of::<T>() == align_of::<W::Pointee>(),
+            "attempted to cast a reference to a wrapper with an incompatible layout",
+        );
+
+        W::from_raw(self.subfield_unchecked(self.get_unchecked().cast::<W::Pointee>()))
+    }
+
+    #[cfg(feature = "debug-tools")]
+    pub fn walk_debug(
+        self,
+        mut step: impl FnMut(Ref<T>) -> Option<Ref<T>>,
+        mut fmt: impl FnMut(Ref<T>) -> String,
+    ) -> String {
+        let mut out = String::new();
+        let mut curr = Some(self);
+
+        while let Some(node) = curr {
+            if !node.is_alive() {
+                out.push_str("<dead>");
+                return out;
+            }
+
+            out.push_str(&fmt(node));
+            out.push_str(" -> ");
+            curr = step(node);
+        }
+
+        out.push_str("<end>");
+        out
+    }
+}
+
+impl<T, const N: usize> Ref<[T; N]> {
+    #[inline(always)]
+    pub fn as_slice(self) -> Ref<[T]> {
+        unsafe { self.subfield_unchecked(self.get_unchecked() as *mut [T]) }
+    }
+}
+
+impl<T> Ref<[T]> {
+    /// The length of the referenced slice, which is known even once the reference is dead.
+    #[inline(always)]
+    pub fn len(self) -> usize {
+        self.value.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    /// Projects to the element at `index`, returning `None` if it's out of bounds or the slot is
+    /// dead.
+    pub fn try_index(self, index: usize) -> Option<Ref<T>> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let ptr = self.try_get()?.cast::<T>();
+        Some(unsafe { self.subfield_unchecked(ptr.add(index)) })
+    }
+
+    #[track_caller]
+    pub fn index(self, index: usize) -> Ref<T> {
+        assert!(
+            index < self.len(),
+            "index {index} out of range for slice of length {}",
+            self.len(),
+        );
+
+        match self.try_index(index) {
+            Some(elem) => elem,
+            None => self.panic_dangling(),
+        }
+    }
+
+    /// Projects to the elements in `range`, returning `None` if it's out of bounds or the slot is
+    /// dead.
+    pub fn try_subslice(self, range: impl RangeBounds<usize>) -> Option<Ref<[T]>> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+
+        if start > end || end > self.len() {
+            return None;
+        }
+
+        let ptr = self.try_get()?.cast::<T>();
+        let sub = std::ptr::slice_from_raw_parts_mut(unsafe { ptr.add(start) }, end - start);
+        Some(unsafe { self.subfield_unchecked(sub) })
+    }
+
+    #[track_caller]
+    pub fn subslice(self, range: impl RangeBounds<usize>) -> Ref<[T]> {
+        if !self.is_alive() {
+            self.panic_dangling();
+        }
+
+        self.try_subslice(range)
+            .unwrap_or_else(|| panic!("subslice out of range for slice of length {}", self.len()))
+    }
+}
+
+/// Lets methods take `self: Ref<Self>` on nightly.
+#[cfg(feature = "nightly")]
+impl<T: ?Sized> std::ops::Receiver for Ref<T> {
+    type Target = T;
+}
+
+/// Refs are ordered by generation, which is unique to each object. An allocation issues increasing
+/// generations, so refs into one allocation are ordered by when their objects were put there.
+/// Across allocations, refs are ordered by when the blocks their generations came from were
+/// reserved from the global counter. That order is only reproducible across runs if the program
+/// reserves blocks in the same order each time, which multithreaded programs generally don't. Refs to different
+/// parts of the same object are ordered by address.
+impl<T: ?Sized> Ord for Ref<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.gen(), self.value, self.tag).cmp(&(other.gen(), other.value, other.tag))
+    }
+}
+
+impl<T: ?Sized> PartialOrd for Ref<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ref")
+            .field("gen", &self.gen())
+            .field("addr", &self.value.cast::<()>())
+            .field("alive", &self.is_alive())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Ref<T> {
+    /// Formats like the reference's own `Debug` impl but also includes the value if it's alive and
+    /// not mutably borrowed.
+    pub fn debug_value(self) -> impl fmt::Debug
+    where
+        T: fmt::Debug,
+    {
+        struct DebugValue<T: ?Sized + 'static>(Ref<T>);
+
+        impl<T: ?Sized + fmt::Debug> fmt::Debug for DebugValue<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = f.debug_struct("Ref");
+                f.field("gen", &self.0.gen())
+                    .field("addr", &self.0.value.cast::<()>())
+                    .field("alive", &self.0.is_alive());
+
+                if let Some(value) = self.0.try_borrow() {
+                    f.field("value", &&*value);
+                }
+
+                f.finish()
+            }
+        }
+
+        DebugValue(self)
+    }
+}
+
+/// A plain-data identifier for an object which can be logged, serialized, or handed to other
+/// processes.
+///
+/// Unlike a [`Ref`], it never points to memory on its own. It has to be resolved back into a `Ref`
+/// through the object DB of the thread which created the object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjId(pub NonZeroU64);
+
+impl ObjId {
+    /// Packs the id into a single non-zero integer for runtimes which can only hold integers.
+    pub fn to_bits(self) -> u64 {
+        self.0.get()
+    }
+
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        NonZeroU64::new(bits).map(Self)
+    }
+
+    #[track_caller]
+    pub fn resolve<T: 'static>(self) -> Option<Ref<T>> {
+        Ref::find_by_gen(self.0)
+    }
+}
+
+/// The parts of a [`Ref`] laid out for foreign code.
+///
+/// Foreign code may check liveness itself by comparing `*gen_ptr` against `gen` before touching
+/// `value`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FfiRef {
+    pub gen_ptr: *const u64,
+    pub gen: u64,
+    pub value: *mut std::ffi::c_void,
+}
+
+impl FfiRef {
+    pub fn new<T>(raw: Ref<T>) -> Self {
+        Self {
+            gen_ptr: raw.gen_ptr().as_ptr(),
+            gen: raw.gen().get(),
+            value: raw.value.as_ptr().cast(),
+        }
+    }
+
+    /// Converts the handle back into a [`Ref`].
+    ///
+    /// # Safety
+    ///
+    /// The handle must have been created from a `Ref<T>` of the same `T` and left unmodified.
+    pub unsafe fn into_ref<T>(self) -> Ref<T> {
+        Ref::from_raw_parts(
+            NonNull::new_unchecked(self.gen_ptr.cast_mut()),
+            NonZeroU64::new_unchecked(self.gen),
+            NonNull::new_unchecked(self.value.cast()),
+        )
+    }
+}
+
+impl fmt::Display for ObjId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+impl<T: ?Sized> From<Ref<T>> for ObjId {
+    fn from(raw: Ref<T>) -> Self {
+        raw.id()
+    }
+}
+
+/// A type-erased [`Ref`] tagged with its pointee type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ErasedRef {
+    type_id: TypeId,
+    value: NonNull<()>,
+    tag: NonZeroU64,
+}
+
+impl ErasedRef {
+    pub fn new<T: 'static>(raw: Ref<T>) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            value: raw.value.cast(),
+            tag: raw.tag,
+        }
+    }
+
+    pub fn type_id(self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn is<T: 'static>(self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    pub fn is_alive(self) -> bool {
+        Ref::<()> {
+            value: self.value,
+            tag: self.tag,
+        }
+        .is_alive()
+    }
+
+    pub fn downcast<T: 'static>(self) -> Option<Ref<T>> {
+        self.is::<T>().then(|| Ref {
+            value: self.value.cast(),
+            tag: self.tag,
+        })
+    }
+}
+
+impl<T: 'static> From<Ref<T>> for ErasedRef {
+    fn from(raw: Ref<T>) -> Self {
+        Self::new(raw)
+    }
+}
+
+/// A shared borrow of an object obtained through [`Ref::borrow`].
+pub struct RefGuard<'a, T: ?Sized + 'static> {
+    gen: NonZeroU64,
+    borrowed: &'a T,
+    // Borrows are tracked by thread so the guard has to be released on the same thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T: ?Sized> std::ops::Deref for RefGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.borrowed
+    }
+}
+
+impl<T: ?Sized> Drop for RefGuard<'_, T> {
+    fn drop(&mut self) {
+        db::release_shared_borrow(self.gen);
+    }
+}
+
+/// An exclusive borrow of an object obtained through [`Ref::borrow_mut`].
+pub struct RefMutGuard<'a, T: ?Sized + 'static> {
+    gen: NonZeroU64,
+    borrowed: &'a mut T,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T: ?Sized> std::ops::Deref for RefMutGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.borrowed
+    }
+}
+
+impl<T: ?Sized> std::ops::DerefMut for RefMutGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.borrowed
+    }
+}
+
+impl<T: ?Sized> Drop for RefMutGuard<'_, T> {
+    fn drop(&mut self) {
+        db::use_borrows(|borrows| borrows.remove(&self.gen));
+    }
+}
+
+/// Keeps an object alive while raw pointers to it are in use, obtained through [`Ref::pin_alive`].
+pub struct AliveGuard<'a, T: ?Sized + 'static> {
+    target: &'a Ref<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T: ?Sized> AliveGuard<'_, T> {
+    pub fn target(&self) -> Ref<T> {
+        *self.target
+    }
+
+    /// Returns a pointer to the value which stays valid until the guard is dropped.
+    pub fn get(&self) -> *mut T {
+        self.target.value.as_ptr()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for AliveGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AliveGuard")
+            .field("target", self.target)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for AliveGuard<'_, T> {
+    fn drop(&mut self) {
+        db::release_shared_borrow(self.target.gen());
+    }
+}
+
+impl<T: ?Sized> Ref<T> {
+    /// Prevents the object from being taken, moved, or overwritten until the guard is dropped,
+    /// returning `None` if it's dead or mutably borrowed.
+    ///
+    /// This counts as a shared borrow, so any attempt to remove the object while pinned panics.
+    #[track_caller]
+    pub fn try_pin_alive(&self) -> Option<AliveGuard<'_, T>> {
+        mem::forget(self.try_borrow()?);
+
+        Some(AliveGuard {
+            target: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    #[track_caller]
+    pub fn pin_alive(&self) -> AliveGuard<'_, T> {
+        if !self.is_alive() {
+            self.panic_dangling();
+        }
+        self.try_pin_alive()
+            .expect("attempted to pin a mutably borrowed object")
+    }
+
+    /// Borrows the object immutably, returning `None` if it's dead or mutably borrowed.
+    ///
+    /// Borrows are tracked per object so borrowing one of its fields conflicts with borrowing the
+    /// whole object. While any borrow is held, the object can't be taken, moved, or overwritten,
+    /// and while a mutable borrow is held it can't be read either.
+    #[track_caller]
+    pub fn try_borrow(&self) -> Option<RefGuard<'_, T>> {
+        let ptr = self.try_get()?;
+        db::assert_token_released();
+
+        db::use_borrows(|borrows| {
+            let count = borrows.entry(self.gen()).or_insert(0);
+            if *count < 0 {
+                return None;
+            }
+
+            *count += 1;
+            Some(())
+        })?;
+
+        Some(RefGuard {
+            gen: self.gen(),
+            borrowed: unsafe { &*ptr },
+            _not_send: PhantomData,
+        })
+    }
+
+    #[track_caller]
+    pub fn borrow(&self) -> RefGuard<'_, T> {
+        if !self.is_alive() {
+            self.panic_dangling();
+        }
+        self.try_borrow()
+            .expect("attempted to borrow a mutably borrowed object")
+    }
+
+    /// Borrows the object mutably, returning `None` if it's dead or already borrowed.
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Option<RefMutGuard<'_, T>> {
+        let ptr = self.try_get()?;
+        db::assert_token_released();
+
+        db::use_borrows(|borrows| match borrows.entry(self.gen()) {
+            hashbrown::hash_map::Entry::Occupied(_) => None,
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                entry.insert(-1);
+                Some(())
+            }
+        })?;
+
+        Some(RefMutGuard {
+            gen: self.gen(),
+            borrowed: unsafe { &mut *ptr },
+            _not_send: PhantomData,
+        })
+    }
+
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMutGuard<'_, T> {
+        if !self.is_alive() {
+            self.panic_dangling();
+        }
+        self.try_borrow_mut()
+            .expect("attempted to mutably borrow an already borrowed object")
+    }
+
+    /// Runs `f` against a shared view of the value, returning `None` if the reference is dead.
+    ///
+    /// Liveness is checked once for the whole closure, so this is cheaper than reading several
+    /// fields through separate accessors. The object is borrowed while `f` runs, so taking or
+    /// overwriting it from inside `f` panics.
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_with<R>(self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        if !self.is_alive() {
+            return None;
+        }
+
+        Some(f(&self.borrow()))
+    }
+
+    #[track_caller]
+    pub fn with<R>(self, f: impl FnOnce(&T) -> R) -> R {
+        match self.try_with(f) {
+            Some(res) => res,
+            None => self.panic_dangling(),
+        }
+    }
+
+    /// Runs `f` against the value in place, returning `None` if the reference is dead.
+    ///
+    /// The object is mutably borrowed while `f` runs, so accessing it through another handle from
+    /// inside `f` panics.
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_update<R>(self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if !self.is_alive() {
+            return None;
+        }
+
+        Some(f(&mut self.borrow_mut()))
+    }
+
+    #[track_caller]
+    pub fn update<R>(self, f: impl FnOnce(&mut T) -> R) -> R {
+        match self.try_update(f) {
+            Some(res) => res,
+            None => self.panic_dangling(),
+        }
+    }
+
+    /// Clones the value out of the slot, returning `None` if the reference is dead.
+    ///
+    /// The value is borrowed while it's cloned so `Clone` impls can't take or overwrite it.
+    #[track_caller]
+    pub fn try_cloned(self) -> Option<T>
+    where
+        T: Clone,
+    {
+        if !self.is_alive() {
+            return None;
+        }
+
+        Some(T::clone(&self.borrow()))
+    }
+
+    #[track_caller]
+    pub fn cloned(self) -> T
+    where
+        T: Clone,
+    {
+        match self.try_cloned() {
+            Some(value) => value,
+            None => self.panic_dangling(),
+        }
+    }
+
+    /// Runs `f` against the value in place without checking liveness or borrows.
+    ///
+    /// # Safety
+    ///
+    /// The reference must be alive, the value must not be borrowed, and `f` must not access the
+    /// value through any other path.
+    pub unsafe fn update_unchecked<R>(self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut *self.get_unchecked())
+    }
+}
+
+#[macro_export]
+macro_rules! subfield {
+    ($target:expr, $field:ident) => {{
+        let (target, ptr) =
+            $target.get_for_macro($crate::subfield_internals::get_func_disambiguator());
+
+        let ptr = unsafe {
+            // Safety: this is a valid pointer to some data.
+            $crate::subfield_internals::addr_of_mut!((*ptr).$field)
+        };
+
+        unsafe {
+            // Safety: this field will not expire until the parent structure has expired.
+            target.subfield_unchecked(ptr)
+        }
+    }};
+}
+
+/// Coerces a `Ref<T>` into a `Ref<U>` wherever `*mut T` coerces to `*mut U`, e.g. `Ref<dyn Trait>`.
+#[macro_export]
+macro_rules! unsize {
+    ($target:expr, $ty:ty) => {{
+        let target = $target;
+
+        unsafe {
+            // Safety: the pointer is only ever changed by an unsizing coercion.
+            target.unsize_unchecked(|ptr| -> *mut $ty { ptr })
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! try_subfield {
+    ($target:expr, $field:ident) => {{
+        match $target.try_get_for_macro($crate::subfield_internals::get_func_disambiguator()) {
+            ::core::option::Option::Some((target, ptr)) => {
+                let ptr = unsafe {
+                    // Safety: this is a valid pointer to some data.
+                    $crate::subfield_internals::addr_of_mut!((*ptr).$field)
+                };
+
+                ::core::option::Option::Some(unsafe {
+                    // Safety: this field will not expire until the parent structure has expired.
+                    target.subfield_unchecked(ptr)
+                })
+            }
+            ::core::option::Option::None => ::core::option::Option::None,
+        }
+    }};
+}
+
+#[doc(hidden)]
+pub mod subfield_internals {
+    use super::*;
+
+    pub use std::ptr::addr_of_mut;
+
+    #[inline(always)]
+    pub fn get_func_disambiguator() -> FuncDisambiguator {
+        FuncDisambiguator
+    }
+}
+
+mod func_disambiguator_sealed {
+    pub struct FuncDisambiguator;
+}
+
+use derive_where::derive_where;
+pub(crate) use func_disambiguator_sealed::FuncDisambiguator;
+
+// === Token === //
+
+/// A per-thread capability for accessing objects through plain references.
+///
+/// [`Ref::get_ref`] and [`Ref::get_mut`] tie the references they return to a borrow of the token,
+/// so the borrow checker rules out aliasing between them at compile time. While the token exists,
+/// every other access to live objects panics since it could alias them, be it a `read`, `write`,
+/// `with`, borrow, take, or move. Those can be used again inside [`Token::unlocked`]. Only the
+/// raw pointers returned by `get` stay as unchecked as any other raw pointer access.
+pub struct Token {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Token {
+    /// Acquires this thread's token, returning `None` if it's already held or if any object is
+    /// currently borrowed.
+    pub fn try_acquire() -> Option<Self> {
+        if db::token_held() || !db::use_borrows(|borrows| borrows.is_empty()) {
+            return None;
+        }
+
+        db::set_token_held(true);
+        Some(Self {
+            _not_send: PhantomData,
+        })
+    }
+
+    #[track_caller]
+    pub fn acquire() -> Self {
+        Self::try_acquire()
+            .expect("attempted to acquire a token while it or a borrow was already held")
+    }
+
+    /// Runs `f` with the token-less access paths enabled again.
+    ///
+    /// Borrows created by `f` must be released before it returns.
+    #[track_caller]
+    pub fn unlocked<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        struct Relock;
+
+        impl Drop for Relock {
+            fn drop(&mut self) {
+                db::set_token_held(true);
+            }
+        }
+
+        db::set_token_held(false);
+        let relock = Relock;
+        let res = f();
+
+        assert!(
+            db::use_borrows(|borrows| borrows.is_empty()),
+            "a borrow escaped `Token::unlocked`",
+        );
+        drop(relock);
+
+        res
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        db::set_token_held(false);
+    }
+}
+
+impl<T: ?Sized> Ref<T> {
+    pub fn try_get_ref(self, _token: &Token) -> Option<&T> {
+        self.try_get().map(|ptr| unsafe { &*ptr })
+    }
+
+    #[track_caller]
+    pub fn get_ref(self, token: &Token) -> &T {
+        match self.try_get_ref(token) {
+            Some(value) => value,
+            None => self.panic_dangling(),
+        }
+    }
+
+    pub fn try_get_mut(self, _token: &mut Token) -> Option<&mut T> {
+        self.try_get().map(|ptr| unsafe { &mut *ptr })
+    }
+
+    #[track_caller]
+    pub fn get_mut(self, token: &mut Token) -> &mut T {
+        match self.try_get_mut(token) {
+            Some(value) => value,
+            None => self.panic_dangling(),
+        }
+    }
+}
+
+// === MovableRef === //
+
+#[derive_where(Clone)]
+pub struct MovableRef<T: 'static> {
+    // Where the object was last seen. Its generation and offset stay the same across moves.
+    raw: Cell<Ref<T>>,
+    #[cfg(debug_assertions)]
+    owner: std::thread::ThreadId,
+}
+
+impl<T> MovableRef<T> {
+    pub fn new(raw: Ref<T>) -> Self {
+        Self {
+            raw: Cell::new(raw),
+            #[cfg(debug_assertions)]
+            owner: std::thread::current().id(),
+        }
+    }
+
+    pub fn id(&self) -> ObjId {
+        self.raw.get().id()
+    }
+
+    /// Returns the reference if the object is still alive where it was last seen.
+    pub fn resolve(&self) -> Option<Ref<T>> {
+        let resolved = self.force_resolve_prim();
+        resolved.is_alive().then_some(resolved)
+    }
+
+    /// Returns the reference if the object is still alive, following it if it has moved.
+    pub fn try_resolve(&self) -> Option<Ref<T>> {
+        let resolved = self.repair_resolve_prim();
+        resolved.is_alive().then_some(resolved)
+    }
+
+    pub fn force_resolve_prim(&self) -> Ref<T> {
+        self.raw.get()
+    }
+
+    pub fn force_resolve(&self) -> T::Wrapper
+    where
+        T: Struct,
+    {
+        self.force_resolve_prim().wrap()
+    }
+
+    pub fn repair_resolve_prim(&self) -> Ref<T> {
+        let resolved = self.force_resolve_prim();
+        if resolved.is_alive() {
+            return resolved;
+        }
+
+        // The object DB is thread-local so resolving on another thread would silently consult the
+        // wrong DB.
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.owner,
+            std::thread::current().id(),
+            "attempted to resolve a `MovableRef` on a thread other than the one which created it",
+        );
+
+        let relocated = resolved.relocated();
+        self.raw.set(relocated);
+
+        relocated
+    }
+
+    pub fn repair_resolve(&self) -> T::Wrapper
+    where
+        T: Struct,
+    {
+        self.repair_resolve_prim().wrap()
+    }
+}
+
+impl<T> From<Ref<T>> for MovableRef<T> {
+    fn from(raw: Ref<T>) -> Self {
+        Self::new(raw)
+    }
+}
+
+/// Movable refs compare and hash by identity: two refs are equal if they point to the same place in
+/// the same incarnation of an object, wherever it has moved since.
+impl<T> PartialEq for MovableRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let (this, other) = (self.raw.get(), other.raw.get());
+        this.gen() == other.gen() && this.offset() == other.offset()
+    }
+}
+
+impl<T> Eq for MovableRef<T> {}
+
+impl<T> Hash for MovableRef<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let raw = self.raw.get();
+        raw.gen().hash(state);
+        raw.offset().hash(state);
+    }
+}
+
+impl<T> fmt::Debug for MovableRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resolved = self.try_resolve();
+        f.debug_struct("MovableRef")
+            .field("gen", &self.raw.get().gen())
+            .field("addr", &self.raw.get().value.cast::<()>())
+            .field("alive", &resolved.is_some())
+            .finish()
+    }
+}
+
+/// A [`MovableRef`] which can be stored in `Send` and `Sync` structures.
+///
+/// It records only the object's generation and the allocation it was created in, and re-resolves
+/// through the object DB on every access. The DB is thread-local so resolving it on any thread other
+/// than the one which created it panics.
+#[derive_where(Debug, Copy, Clone)]
+pub struct SendMovableRef<T: 'static> {
+    gen: NonZeroU64,
+    alloc: Allocation<T>,
+    owner: std::thread::ThreadId,
+}
+
+// Safety: the allocation handle is only ever dereferenced on the thread which owns it.
+unsafe impl<T> Send for SendMovableRef<T> {}
+unsafe impl<T> Sync for SendMovableRef<T> {}
+
+impl<T> SendMovableRef<T> {
+    #[track_caller]
+    pub fn new(raw: Ref<T>) -> Self {
+        let alloc = raw
+            .allocation()
+            .expect("attempted to make a sendable reference to a dead object or part of one");
+
+        Self {
+            gen: raw.gen(),
+            alloc,
+            owner: std::thread::current().id(),
+        }
+    }
+
+    pub fn id(self) -> ObjId {
+        ObjId(self.gen)
+    }
+
+    pub fn owner(self) -> std::thread::ThreadId {
+        self.owner
+    }
+
+    #[track_caller]
+    pub fn resolve(self) -> Option<Ref<T>> {
+        assert_eq!(
+            self.owner,
+            std::thread::current().id(),
+            "attempted to resolve a `SendMovableRef` on a thread other than the one which created it",
+        );
+
+        let gen_ptr = db::use_object_db(|db| db.get(&self.gen).copied())?;
+
+        // Looking in the allocation the object was created in first skips the page registry search
+        // for objects which never left it.
+        if self.alloc.is_alive() {
+            if let Some(index) = self.alloc.slot_index(gen_ptr) {
+                let slot = self.alloc.slot(index);
+                return (slot.gen() == self.gen.get()).then(|| slot.make_ref(self.gen));
+            }
+        }
+
+        Ref::find_by_gen(self.gen)
+    }
+
+    #[track_caller]
+    pub fn is_alive(self) -> bool {
+        self.resolve().is_some()
+    }
+
+    #[track_caller]
+    pub fn get(self) -> Ref<T> {
+        self.resolve().expect(DANGLING_ERR)
+    }
+}
+
+impl<T> From<Ref<T>> for SendMovableRef<T> {
+    #[track_caller]
+    fn from(raw: Ref<T>) -> Self {
+        Self::new(raw)
+    }
+}
+
+// === Strong === //
+
+/// A reference-counted owning handle to an object.
+///
+/// The object is destroyed once the last `Strong` handle to it is dropped while plain [`Ref`]s to
+/// it stay weak. Counts are kept in a per-thread side table keyed by generation, like borrows, so
+/// slots of objects which are never shared this way pay nothing for them.
+pub struct Strong<T: 'static> {
+    raw: Ref<T>,
+}
+
+impl<T> Strong<T> {
+    /// Takes shared ownership of the object behind `raw`.
+    ///
+    /// If other `Strong` handles to it exist, this joins them.
+    #[track_caller]
+    pub fn new(raw: Ref<T>) -> Self {
+        if !raw.is_alive() {
+            raw.panic_dangling();
+        }
+
+        db::use_strong_counts(|counts| *counts.entry(raw.gen()).or_insert(0) += 1);
+        Self { raw }
+    }
+
+    /// Spawns `value` into the global arena for `T` and takes ownership of it.
+    pub fn spawn(value: T) -> Self {
+        Self::new(spawn(value))
+    }
+
+    /// Returns a new strong handle if the object is alive and still strongly held.
+    pub fn upgrade(raw: Ref<T>) -> Option<Self> {
+        if !raw.is_alive() {
+            return None;
+        }
+
+        db::use_strong_counts(|counts| {
+            let count = counts.get_mut(&raw.gen())?;
+            *count += 1;
+            Some(Self { raw })
+        })
+    }
+
+    pub fn downgrade(&self) -> Ref<T> {
+        self.raw
+    }
+
+    /// Returns the number of strong handles to the object, or zero if it's been destroyed by other
+    /// means.
+    pub fn strong_count(&self) -> usize {
+        if !self.raw.is_alive() {
+            return 0;
+        }
+
+        db::use_strong_counts(|counts| counts.get(&self.raw.gen()).copied().unwrap_or(0))
+    }
+}
+
+impl<T> Clone for Strong<T> {
+    fn clone(&self) -> Self {
+        db::use_strong_counts(|counts| *counts.entry(self.raw.gen()).or_insert(0) += 1);
+        Self { raw: self.raw }
+    }
+}
+
+impl<T> Drop for Strong<T> {
+    fn drop(&mut self) {
+        let last = db::use_strong_counts(|counts| {
+            let hashbrown::hash_map::Entry::Occupied(mut entry) = counts.entry(self.raw.gen())
+            else {
+                unreachable!();
+            };
+
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+                true
+            } else {
+                false
+            }
+        });
+
+        // The value's destructor may drop other handles so it must run outside the table.
+        if last {
+            drop(self.raw.try_destroy());
+        }
+    }
+}
+
+impl<T> fmt::Debug for Strong<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Strong").field("raw", &self.raw).finish()
+    }
+}
+
+// === Owned === //
+
+/// A unique owning handle which destroys its object when dropped.
+///
+/// Plain [`Ref`]s to the object can still be handed out through [`Owned::raw`]; they simply die
+/// along with it.
+pub struct Owned<T: 'static> {
+    raw: Ref<T>,
+}
+
+impl<T> Owned<T> {
+    /// Takes ownership of the object behind `raw`.
+    #[track_caller]
+    pub fn new(raw: Ref<T>) -> Self {
+        if !raw.is_alive() {
+            raw.panic_dangling();
+        }
+
+        Self { raw }
+    }
+
+    /// Spawns `value` into the global arena for `T` and takes ownership of it.
+    pub fn spawn(value: T) -> Self {
+        Self::new(spawn(value))
+    }
+
+    pub fn raw(&self) -> Ref<T> {
+        self.raw
+    }
+
+    /// Takes the value out of its slot, returning `None` if it was already destroyed by other
+    /// means.
+    pub fn try_into_inner(self) -> Option<T> {
+        ManuallyDrop::new(self).raw.try_destroy()
+    }
+
+    #[track_caller]
+    pub fn into_inner(self) -> T {
+        ManuallyDrop::new(self).raw.destroy()
+    }
+
+    /// Gives up ownership, leaving the object alive until it's destroyed by other means.
+    pub fn leak(self) -> Ref<T> {
+        ManuallyDrop::new(self).raw
+    }
+}
+
+impl<T> Drop for Owned<T> {
+    fn drop(&mut self) {
+        drop(self.raw.try_destroy());
+    }
+}
+
+impl<T> fmt::Debug for Owned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Owned").field("raw", &self.raw).finish()
+    }
+}
+
+// === PinnedRef === //
+
+/// A handle which keeps its object from being relocated to another slot while it exists.
+///
+/// [`Allocation::compact`] leaves pinned objects where they are and every other move panics. Pins
+/// are counted so that an object stays pinned until the last of its handles is dropped.
+pub struct PinnedRef<T: 'static> {
+    raw: Ref<T>,
+}
+
+impl<T> PinnedRef<T> {
+    pub fn raw(&self) -> Ref<T> {
+        self.raw
+    }
+
+    /// Releases this pin, returning the plain reference.
+    pub fn unpin(self) -> Ref<T> {
+        self.raw
+    }
+}
+
+impl<T> Clone for PinnedRef<T> {
+    fn clone(&self) -> Self {
+        db::use_pins(|pins| *pins.entry(self.raw.gen()).or_insert(0) += 1);
+        Self { raw: self.raw }
+    }
+}
+
+impl<T> Drop for PinnedRef<T> {
+    fn drop(&mut self) {
+        db::use_pins(|pins| {
+            let hashbrown::hash_map::Entry::Occupied(mut entry) = pins.entry(self.raw.gen()) else {
+                unreachable!();
+            };
+
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        });
+    }
+}
+
+impl<T> fmt::Debug for PinnedRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinnedRef").field("raw", &self.raw).finish()
+    }
+}
+
+// === ThinRef === //
+
+/// A one-word handle to a whole object, resolved through the object DB on every access.
+///
+/// Resolving costs a DB lookup and a page registry search rather than a single load, so this
+/// trades speed for half the memory of a [`Ref`]. Thin refs keep resolving to their object
+/// when it's moved to another slot.
+#[derive_where(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThinRef<T: 'static> {
+    gen: NonZeroU64,
+    _ty: PhantomData<fn() -> T>,
+}
+
+impl<T> ThinRef<T> {
+    #[track_caller]
+    pub fn new(raw: Ref<T>) -> Self {
+        debug_assert!(
+            !raw.is_alive() || raw.allocation().is_some(),
+            "attempted to make a thin reference to part of an object",
+        );
+
+        Self {
+            gen: raw.gen(),
+            _ty: PhantomData,
+        }
+    }
+
+    pub fn id(self) -> ObjId {
+        ObjId(self.gen)
+    }
+
+    #[track_caller]
+    pub fn resolve(self) -> Option<Ref<T>> {
+        Ref::find_by_gen(self.gen)
+    }
+
+    #[track_caller]
+    pub fn is_alive(self) -> bool {
+        self.resolve().is_some()
+    }
+
+    #[track_caller]
+    pub fn get(self) -> Ref<T> {
+        self.resolve().expect(DANGLING_ERR)
+    }
+}
+
+impl<T> From<Ref<T>> for ThinRef<T> {
+    fn from(raw: Ref<T>) -> Self {
+        Self::new(raw)
+    }
+}
+
+// === Serde === //
+
+#[cfg(feature = "serde")]
+mod serde_support {
+    use std::{cell::Cell, ptr::NonNull};
+
+    use super::*;
+
+    thread_local! {
+        static SERIALIZE_MAP: Cell<Option<NonNull<SerializeMap>>> = const { Cell::new(None) };
+        static DESERIALIZE_MAP: Cell<Option<NonNull<DeserializeMap>>> = const { Cell::new(None) };
+    }
+
+    /// Assigns stable ids to the references serialized while it's in [`scope`](Self::scope).
+    ///
+    /// Ids start at 1 and count up in the order references are first seen. Dead references
+    /// serialize as id 0.
+    #[derive(Debug, Default)]
+    pub struct SerializeMap {
+        ids: hashbrown::HashMap<ErasedRef, u64>,
+        refs: Vec<ErasedRef>,
+    }
+
+    impl SerializeMap {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Runs `f` with this map used for every [`Ref`] serialized on this thread.
+        pub fn scope<R>(&mut self, f: impl FnOnce() -> R) -> R {
+            let _guard = ScopeGuard::install(&SERIALIZE_MAP, NonNull::from(self));
+            f()
+        }
+
+        pub fn id_of<T: 'static>(&mut self, raw: Ref<T>) -> u64 {
+            if !raw.is_alive() {
+                return 0;
+            }
+
+            let raw = ErasedRef::new(raw);
+            *self.ids.entry(raw).or_insert_with(|| {
+                self.refs.push(raw);
+                self.refs.len() as u64
+            })
+        }
+
+        /// Returns every reference assigned an id so far, where the reference with id `n` is at
+        /// index `n - 1`.
+        pub fn refs(&self) -> &[ErasedRef] {
+            &self.refs
+        }
+    }
+
+    /// Maps the ids written by a [`SerializeMap`] to the references they should deserialize to.
+    #[derive(Debug, Default)]
+    pub struct DeserializeMap {
+        refs: hashbrown::HashMap<u64, ErasedRef>,
+    }
+
+    impl DeserializeMap {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Runs `f` with this map used for every [`Ref`] deserialized on this thread.
+        pub fn scope<R>(&mut self, f: impl FnOnce() -> R) -> R {
+            let _guard = ScopeGuard::install(&DESERIALIZE_MAP, NonNull::from(self));
+            f()
+        }
+
+        pub fn insert<T: 'static>(&mut self, id: u64, raw: Ref<T>) -> Option<ErasedRef> {
+            assert_ne!(id, 0, "id 0 is reserved for dead references");
+            self.refs.insert(id, ErasedRef::new(raw))
+        }
+
+        pub fn get(&self, id: u64) -> Option<ErasedRef> {
+            self.refs.get(&id).copied()
+        }
+    }
+
+    /// Restores the previously installed map when a scope ends, even by unwinding.
+    struct ScopeGuard<M: 'static> {
+        slot: &'static std::thread::LocalKey<Cell<Option<NonNull<M>>>>,
+        prev: Option<NonNull<M>>,
+    }
+
+    impl<M> ScopeGuard<M> {
+        fn install(
+            slot: &'static std::thread::LocalKey<Cell<Option<NonNull<M>>>>,
+            map: NonNull<M>,
+        ) -> Self {
+            Self {
+                slot,
+                prev: slot.with(|v| v.replace(Some(map))),
+            }
+        }
+    }
+
+    impl<M> Drop for ScopeGuard<M> {
+        fn drop(&mut self) {
+            self.slot.with(|v| v.set(self.prev));
+        }
+    }
+
+    impl<T: 'static> serde::Serialize for Ref<T> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let Some(mut map) = SERIALIZE_MAP.with(Cell::get) else {
+                return Err(serde::ser::Error::custom(
+                    "attempted to serialize a `Ref` outside of a `SerializeMap` scope",
+                ));
+            };
+
+            // The map is exclusively borrowed by `scope` for as long as it's installed.
+            serializer.serialize_u64(unsafe { map.as_mut() }.id_of(*self))
+        }
+    }
+
+    impl<'de, T: 'static> serde::Deserialize<'de> for Ref<T> {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            use serde::de::Error;
+
+            let id = u64::deserialize(deserializer)?;
+            if id == 0 {
+                return Ok(Ref::dangling());
+            }
+
+            let Some(map) = DESERIALIZE_MAP.with(Cell::get) else {
+                return Err(D::Error::custom(
+                    "attempted to deserialize a `Ref` outside of a `DeserializeMap` scope",
+                ));
+            };
+
+            let raw = unsafe { map.as_ref() }
+                .get(id)
+                .ok_or_else(|| D::Error::custom(format_args!("no reference with id {id}")))?;
+
+            raw.downcast().ok_or_else(|| {
+                D::Error::custom(format_args!(
+                    "reference with id {id} doesn't point to a `{}`",
+                    std::any::type_name::<T>(),
+                ))
+            })
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use serde_support::{DeserializeMap, SerializeMap};
+
+// === Relocate === //
+
+/// Values holding references which should follow their objects when they're moved to another slot.
+///
+/// Moving an object leaves plain [`Ref`]s to it dangling. [`Allocation::compact_relocating`] calls
+/// this on every live value once it's done moving them so references between them stay valid. It
+/// can be derived with `#[derive(Relocate)]`, skipping fields marked `#[relocate(skip)]`.
+pub trait Relocate {
+    fn relocate(&mut self);
+}
+
+impl<T> Relocate for Ref<T> {
+    #[track_caller]
+    fn relocate(&mut self) {
+        *self = self.relocated();
+    }
+}
+
+impl<T> Relocate for Strong<T> {
+    #[track_caller]
+    fn relocate(&mut self) {
+        self.raw.relocate();
+    }
+}
+
+impl<T> Relocate for Owned<T> {
+    #[track_caller]
+    fn relocate(&mut self) {
+        self.raw.relocate();
+    }
+}
+
+impl<T: Relocate> Relocate for Option<T> {
+    fn relocate(&mut self) {
+        if let Some(value) = self {
+            value.relocate();
+        }
+    }
+}
+
+impl<T: Relocate + ?Sized> Relocate for Box<T> {
+    fn relocate(&mut self) {
+        (**self).relocate();
+    }
+}
+
+impl<T: Relocate> Relocate for [T] {
+    fn relocate(&mut self) {
+        self.iter_mut().for_each(T::relocate);
+    }
+}
+
+impl<T: Relocate, const N: usize> Relocate for [T; N] {
+    fn relocate(&mut self) {
+        self.iter_mut().for_each(T::relocate);
+    }
+}
+
+impl<T: Relocate> Relocate for Vec<T> {
+    fn relocate(&mut self) {
+        self.iter_mut().for_each(T::relocate);
+    }
+}
+
+impl<T: Relocate> Relocate for VecDeque<T> {
+    fn relocate(&mut self) {
+        self.iter_mut().for_each(T::relocate);
+    }
+}
+
+macro_rules! impl_relocate_tuple {
+    ($($para:ident),*) => {
+        impl<$($para: Relocate),*> Relocate for ($($para,)*) {
+            #[allow(non_snake_case)]
+            fn relocate(&mut self) {
+                let ($($para,)*) = self;
+                $($para.relocate();)*
+            }
+        }
+    };
+}
+
+impl_relocate_tuple!(A);
+impl_relocate_tuple!(A, B);
+impl_relocate_tuple!(A, B, C);
+impl_relocate_tuple!(A, B, C, D);
+
+macro_rules! impl_relocate_noop {
+    ($($ty:ty),*$(,)?) => {$(
+        impl Relocate for $ty {
+            fn relocate(&mut self) {}
+        }
+    )*};
+}
+
+// These either hold no references or find their objects by generation.
+impl_relocate_noop!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    String,
+    &'static str,
+    ObjId,
+);
+
+impl<T> Relocate for ThinRef<T> {
+    fn relocate(&mut self) {}
+}
+
+impl<T> Relocate for MovableRef<T> {
+    fn relocate(&mut self) {}
+}
+
+impl<T> Relocate for PinnedRef<T> {
+    fn relocate(&mut self) {}
+}
+
+// === Struct === //
+
+pub trait Struct: 'static {
+    type Wrapper: RefWrapper<Pointee = Self>;
+}
+
+pub trait RefWrapper: Copy {
+    type Pointee: 'static;
+
+    fn from_raw(raw: Ref<Self::Pointee>) -> Self;
+
+    fn raw(self) -> Ref<Self::Pointee>;
+}
+
+// === Macros === //
+
+pub extern crate self as bees;
+
+pub use bees_macro::{Relocate, Struct};
+
+#[doc(hidden)]
+pub mod derive_struct_internal {
+    pub use std::{
+        cmp::Ordering,
+        fmt,
+        hash::{Hash, Hasher},
+    };
+    pub use {Clone, Copy, Eq, FnOnce, Option, Ord, PartialEq, PartialOrd};
 
     pub trait TrivialBound<'__> {
         type Itself: ?Sized;
     }
 
-    impl<T: ?Sized> TrivialBound<'_> for T {
-        type Itself = Self;
+    impl<T: ?Sized> TrivialBound<'_> for T {
+        type Itself = Self;
+    }
+}
+
+// These also run under Miri with `MIRIFLAGS=-Zmiri-ignore-leaks cargo +nightly miri test`. Slot
+// storage is deliberately never handed back since stale refs may still read it, so the tests count
+// drops to check that values don't leak instead of relying on Miri's leak checker.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_by_cannot_free_compared_values() {
+        let alloc = Allocation::<Box<u32>>::new(4);
+        let refs = [3, 1, 2].map(|value| alloc.insert(Box::new(value)).unwrap());
+
+        alloc.sort_by(|a, b| {
+            // Values being compared are detached, so clearing the allocation can't free them.
+            alloc.clear();
+            a.cmp(b)
+        });
+
+        let sorted = alloc
+            .iter_alive()
+            .map(|(_, value)| unsafe { **value.get() })
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, [1, 2, 3]);
+        assert_eq!(alloc.remaining_capacity(), 1);
+        assert!(refs.iter().all(|value| !value.is_alive()));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn failed_detaches_leave_every_slot_alone() {
+        let (alloc, refs) = Allocation::from_values([3u32, 1, 2]);
+
+        let guard = refs[2].borrow();
+        let sort = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            alloc.sort_by(|a, b| a.cmp(b))
+        }));
+        assert!(sort.is_err());
+        drop(guard);
+
+        assert!(refs.iter().all(|value| value.is_alive()));
+        assert_eq!(
+            alloc.iter_values().collect::<Vec<_>>(),
+            [(0, 3), (1, 1), (2, 2)]
+        );
+        assert_eq!(alloc.take(0), Some(3));
+        assert_eq!(alloc.remaining_capacity(), 1);
+        alloc.dealloc();
+    }
+
+    #[derive(Relocate)]
+    struct Node {
+        value: u32,
+        next: Option<Ref<Node>>,
+    }
+
+    #[cfg(feature = "nightly")]
+    impl Node {
+        fn next(self: Ref<Self>) -> Option<Ref<Self>> {
+            self.with(|node| node.next)
+        }
+    }
+
+    #[test]
+    fn snapshot_remaps_internal_refs() {
+        let alloc = Allocation::<Node>::new(2);
+        let leaf = alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: None,
+            },
+        );
+        alloc.put(
+            1,
+            Node {
+                value: 2,
+                next: Some(leaf),
+            },
+        );
+
+        let copy = alloc.snapshot_with(|node, map| Node {
+            value: node.value,
+            next: node.next.and_then(|next| map.map(next)),
+        });
+
+        let copied_next = unsafe { (*copy.get(1).get()).next.unwrap() };
+        assert!(copy.contains(copied_next));
+        assert!(!alloc.contains(copied_next));
+        assert_eq!(unsafe { (*copied_next.get()).value }, 1);
+
+        copy.dealloc();
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn snapshot_cannot_free_cloned_values() {
+        let (alloc, refs) = Allocation::from_values([Box::new(1u32), Box::new(2)]);
+
+        let copy = alloc.snapshot_with(|value, _| {
+            alloc.clear();
+            value.clone()
+        });
+
+        assert!(refs.iter().all(|value| value.is_alive()));
+        assert_eq!(unsafe { **copy.get(1).get() }, 2);
+
+        copy.dealloc();
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn children_are_deallocated_with_parent() {
+        let parent = Allocation::<u32>::new(1);
+        let child = parent.new_child::<u64>(1);
+        let grandchild = child.new_child::<u8>(1);
+        let values = (child.put(0, 1), grandchild.put(0, 2));
+
+        parent.dealloc();
+        assert!(!values.0.is_alive());
+        assert!(!values.1.is_alive());
+    }
+
+    #[test]
+    fn dealloc_refuses_borrowed_or_frozen_allocations() {
+        let parent = Allocation::<u32>::new(1);
+        let child = parent.new_child::<u32>(1);
+        let value = child.put(0, 1);
+
+        let guard = value.borrow();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| parent.dealloc()));
+        assert!(result.is_err());
+        drop(guard);
+
+        let frozen = parent.freeze();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| parent.dealloc()));
+        assert!(result.is_err());
+        drop(frozen);
+
+        // Neither attempt touched the allocations.
+        assert!(child.is_alive());
+        assert_eq!(value.read(), 1);
+
+        parent.dealloc();
+        assert!(!value.is_alive());
+    }
+
+    #[test]
+    #[should_panic = "child of itself"]
+    fn allocation_cannot_be_its_own_child() {
+        let alloc = Allocation::<u32>::new(1);
+        alloc.add_child(alloc);
+    }
+
+    #[test]
+    #[should_panic = "child of itself or of its descendants"]
+    fn allocation_cannot_adopt_its_ancestor() {
+        let parent = Allocation::<u32>::new(1);
+        let child = parent.new_child::<u32>(1);
+        child.add_child(parent);
+    }
+
+    #[test]
+    fn owned_allocation_deallocates_on_drop() {
+        let owned = OwnedAllocation::<u32>::new(1);
+        let value = owned.handle().put(0, 1);
+
+        drop(owned);
+        assert!(!value.is_alive());
+    }
+
+    #[test]
+    fn reclaims_once_emptied() {
+        let alloc = Allocation::<u32>::new(2);
+        let mut reclaiming = alloc.with_auto_reclaim();
+        let first = reclaiming.put(0, 1).unwrap();
+        reclaiming.put(1, 2).unwrap();
+
+        // Emptying a slot through the raw handle still counts.
+        alloc.take(1);
+
+        assert_eq!(reclaiming.take(0), Some(1));
+        assert!(reclaiming.is_reclaimed());
+        assert!(!first.is_alive());
+        assert_eq!(reclaiming.put(0, 3).map(|value| value.read()), None);
+    }
+
+    #[test]
+    fn reclaims_after_retain_and_clear() {
+        let mut retained = Allocation::from_values([1u32, 2]).0.with_auto_reclaim();
+        retained.retain(|_, value| value.read() == 2);
+        assert!(!retained.is_reclaimed());
+        retained.retain(|_, _| false);
+        assert!(retained.is_reclaimed());
+
+        let mut cleared = Allocation::from_values([1u32]).0.with_auto_reclaim();
+        cleared.clear();
+        assert!(cleared.is_reclaimed());
+    }
+
+    #[test]
+    fn raw_parts_round_trip() {
+        let alloc = Allocation::<u32>::new(2);
+        let value = alloc.put(1, 7);
+
+        let (ptr, len) = alloc.into_raw_parts();
+        assert_eq!(len, 2);
+
+        // The documented slot layout: a `u64` generation followed by the value.
+        unsafe {
+            let slot = ptr.as_ptr().add(1).cast::<u8>();
+            assert_ne!(*slot.cast::<u64>(), 0);
+            assert_eq!(*slot.add(size_of::<u64>()).cast::<u32>(), 7);
+            assert_eq!(*ptr.as_ptr().cast::<u64>(), 0);
+        }
+
+        let alloc = unsafe { Allocation::from_raw_parts(ptr, len) };
+        assert!(value.is_alive());
+        assert_eq!(alloc.index_of(value), Some(1));
+        assert_eq!(alloc.get(1).read(), 7);
+
+        // Slots past the first page aren't contiguous with it.
+        alloc.grow(alloc.page_len() + 1);
+        assert!(alloc.try_into_raw_parts().is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn ref_raw_parts_round_trip() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 7);
+
+        let (gen_ptr, gen, ptr) = value.into_raw_parts();
+        let rebuilt = unsafe { Ref::from_raw_parts(gen_ptr, gen, ptr) };
+        assert_eq!(rebuilt, value);
+        assert_eq!(rebuilt.read(), 7);
+
+        assert_eq!(value.as_non_null(), ptr);
+
+        let ffi = FfiRef::new(value);
+        assert_eq!(unsafe { *ffi.gen_ptr }, ffi.gen);
+        assert_eq!(unsafe { ffi.into_ref::<u32>() }, value);
+
+        let bits = value.id().to_bits();
+        assert_eq!(ObjId::from_bits(bits).and_then(ObjId::resolve), Some(value));
+
+        alloc.dealloc();
+        assert!(!rebuilt.is_alive());
+    }
+
+    #[test]
+    fn bump_all_generations_kills_old_refs() {
+        let alloc = Allocation::<u32>::new(2);
+        let old = alloc.put(1, 7);
+
+        let fresh = alloc.bump_all_generations();
+
+        assert!(!old.is_alive());
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(fresh[0].read(), 7);
+
+        #[cfg(feature = "stats")]
+        assert_eq!(alloc.stats().gens_issued, 2);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn dropped_drain_empties_allocation() {
+        let (alloc, refs) = Allocation::from_values(0..4u32);
+
+        assert_eq!(alloc.drain().next(), Some((0, 0)));
+        assert_eq!(alloc.remaining_capacity(), 4);
+        assert!(refs.iter().all(|value| !value.is_alive()));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn extract_if_yields_odd_values() {
+        let (alloc, refs) = Allocation::from_values(0..8u32);
+
+        let mut odd = alloc
+            .extract_if(|value| *value % 2 == 1)
+            .collect::<Vec<_>>();
+        odd.sort();
+
+        assert_eq!(odd, [(1, 1), (3, 3), (5, 5), (7, 7)]);
+        for (value, r) in refs.iter().enumerate() {
+            assert_eq!(r.is_alive(), value % 2 == 0);
+        }
+        assert_eq!(alloc.remaining_capacity(), 4);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn extract_if_cannot_free_inspected_value() {
+        let (alloc, _) = Allocation::from_values([Box::new(1u32), Box::new(2)]);
+
+        let extracted = alloc
+            .extract_if(|value| {
+                if **value == 2 {
+                    alloc.clear();
+                    return true;
+                }
+                false
+            })
+            .map(|(index, value)| (index, *value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(extracted, [(1, 2)]);
+        assert_eq!(alloc.remaining_capacity(), 2);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn interning_same_key_reuses_slot() {
+        let alloc = Allocation::<String>::new(2);
+
+        let first = alloc.get_or_insert_by_key("a", || "a".to_string());
+        let second = alloc.get_or_insert_by_key("a", || unreachable!());
+
+        assert_eq!(first.gen(), second.gen());
+        assert_eq!(first.gen_ptr(), second.gen_ptr());
+        assert_eq!(alloc.remaining_capacity(), 1);
+
+        // Stale entries are replaced transparently.
+        alloc.take_ref(first);
+        let third = alloc.get_or_insert_by_key("a", || "b".to_string());
+        assert!(third.is_alive() && !first.is_alive());
+        assert_eq!(unsafe { &*third.get() }, "b");
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn over_aligned_values_are_aligned() {
+        #[derive(Copy, Clone)]
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let alloc = Allocation::<Aligned>::new(3);
+
+        for index in 0..3 {
+            let value = alloc.put(index, Aligned(index as u8));
+            assert_eq!(value.get().addr() % 64, 0);
+            value.write(Aligned(value.read().0 + 1));
+            assert_eq!(value.read().0, index as u8 + 1);
+        }
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(feature = "debug-tools")]
+    fn walk_debug_marks_dead_link() {
+        let alloc = Allocation::<Node>::new(3);
+        let tail = alloc.put(
+            2,
+            Node {
+                value: 3,
+                next: None,
+            },
+        );
+        let middle = alloc.put(
+            1,
+            Node {
+                value: 2,
+                next: Some(tail),
+            },
+        );
+        let head = alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: Some(middle),
+            },
+        );
+
+        let step = |node: Ref<Node>| unsafe { (*node.get()).next };
+        let fmt = |node: Ref<Node>| unsafe { (*node.get()).value.to_string() };
+
+        assert_eq!(head.walk_debug(step, fmt), "1 -> 2 -> 3 -> <end>");
+        alloc.take(1);
+        assert_eq!(head.walk_debug(step, fmt), "1 -> <dead>");
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn try_new_rejects_overflowing_lengths() {
+        assert!(Allocation::<u64>::try_new(usize::MAX / 8).is_err());
+        assert!(Allocation::<u64>::try_new(isize::MAX as usize / 16 + 1).is_err());
+
+        let alloc = Allocation::<u64>::try_new(4).unwrap();
+        assert_eq!(alloc.len(), 4);
+        alloc.dealloc();
+
+        // Rounding up to a whole page would overflow even though the length itself fits.
+        let len = isize::MAX as usize / 16 - 64;
+        assert!(matches!(
+            Allocation::<u64>::try_new_with(len, GlobalStorage),
+            Err(StorageError::Layout(_)),
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn concurrent_generation_reads_are_not_torn() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+        let gen_ptr = value.gen_ptr().as_ptr().expose_provenance();
+
+        // Every generation written below has matching halves.
+        alloc.slot(0).gen.set(0);
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                let cell = unsafe { &*std::ptr::with_exposed_provenance::<GenCell>(gen_ptr) };
+                for i in 1..10_000u64 {
+                    cell.set(i << 32 | i);
+                }
+            });
+
+            for _ in 0..4 {
+                s.spawn(move || {
+                    for _ in 0..10_000 {
+                        let gen = unsafe {
+                            GenCell::read_ptr(std::ptr::with_exposed_provenance_mut(gen_ptr))
+                        };
+                        assert_eq!(gen >> 32, gen & u64::from(u32::MAX));
+                    }
+                });
+            }
+        });
+
+        alloc.slot(0).gen.set(value.gen().get());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn try_set_drops_old_value_once() {
+        struct Counted<'a>(&'a Cell<u32>);
+
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        // `Allocation` requires `'static` values.
+        let drops: &'static Cell<u32> = Box::leak(Box::new(Cell::new(0)));
+        let alloc = Allocation::<Counted<'static>>::new(1);
+        let value = alloc.put(0, Counted(drops));
+
+        assert!(value.try_set(Counted(drops)));
+        assert_eq!(drops.get(), 1);
+
+        alloc.take(0);
+        assert_eq!(drops.get(), 2);
+        assert!(!value.try_set(Counted(drops)));
+        assert_eq!(drops.get(), 3);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn batches_check_every_slot_before_writing() {
+        let alloc = Allocation::<u32>::new(3);
+        let refs = alloc.put_many([(0, 1), (1, 2)]);
+        assert_eq!(refs.iter().map(|r| r.read()).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(alloc.remaining_capacity(), 1);
+
+        let guard = refs[1].borrow();
+        let put = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            alloc.put_many([(2, 3), (1, 4)])
+        }));
+        let take =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.take_many([0, 1])));
+        assert!(put.is_err() && take.is_err());
+        drop(guard);
+
+        assert!(alloc.try_get(2).is_none());
+        assert!(refs.iter().all(|r| r.is_alive()));
+        assert_eq!(alloc.remaining_capacity(), 1);
+        assert_eq!(alloc.take_many([0, 1, 2]), [Some(1), Some(2), None]);
+        assert_eq!(alloc.remaining_capacity(), 3);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn reserved_object_db_does_not_rehash() {
+        reserve_object_db(1024);
+        let capacity = object_db_capacity();
+        assert!(capacity >= 1024);
+
+        let (alloc, _) = Allocation::from_values(0..1024u32);
+        assert_eq!(object_db_capacity(), capacity);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn try_reinterpret_checks_layout() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, u32::from_ne_bytes([1, 2, 3, 4]));
+
+        let bytes = unsafe { value.try_reinterpret::<[u8; 4]>() }.unwrap();
+        assert_eq!(bytes.read(), [1, 2, 3, 4]);
+        assert!(unsafe { value.try_reinterpret::<u64>() }.is_none());
+        assert!(unsafe { bytes.try_reinterpret::<u32>() }.is_none());
+
+        alloc.take(0);
+        assert!(!bytes.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn stats_count_slot_reuse() {
+        let alloc = Allocation::<u32>::new(2);
+
+        alloc.put(0, 1);
+        alloc.take(0);
+        alloc.put(0, 2);
+        alloc.put(1, 3);
+
+        let stats = alloc.stats();
+        assert_eq!(stats.gens_issued, 3);
+        assert_eq!(stats.puts, 3);
+        assert_eq!(stats.takes, 1);
+        assert_eq!(stats.reuses, 1);
+
+        alloc.dealloc();
+    }
+
+    #[derive(Struct)]
+    #[bees(fallible)]
+    struct Fallible {
+        a: u32,
+    }
+
+    #[derive(Struct)]
+    struct HelperNamed {
+        modify: u32,
+        ptr_eq: bool,
+    }
+
+    #[test]
+    fn fields_can_share_names_with_wrapper_helpers() {
+        let alloc = Allocation::<HelperNamed>::new(1);
+        let value = alloc
+            .put(
+                0,
+                HelperNamed {
+                    modify: 1,
+                    ptr_eq: false,
+                },
+            )
+            .wrap();
+
+        value.set_ptr_eq(true);
+        value.bees_modify(|this| this.modify += 1);
+        assert_eq!((value.modify(), value.ptr_eq()), (2, true));
+        assert!(value.bees_ptr_eq(&value));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn fallible_accessors_return_none_once_taken() {
+        let alloc = Allocation::<Fallible>::new(1);
+        let value = alloc.put(0, Fallible { a: 1 }).wrap();
+
+        assert!(value.set_a(2));
+        assert_eq!(value.a(), Some(2));
+        assert_eq!(
+            value.bees_modify(|this| mem::replace(&mut this.a, 3)),
+            Some(2)
+        );
+        assert!(value.bees_ptr_eq(&value));
+        assert_eq!(value.raw().with(|this| this.a), 3);
+
+        alloc.take(0);
+        assert_eq!(value.a(), None);
+        assert!(!value.set_a(3));
+        assert_eq!(value.bees_modify(|_| ()), None);
+        assert_eq!(value.raw().try_with(|this| this.a), None);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn try_subfield_of_dead_ref_is_none() {
+        let alloc = Allocation::<Node>::new(1);
+        let node = alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: None,
+            },
+        );
+
+        assert_eq!(
+            try_subfield!(node, value).map(|value| value.read()),
+            Some(1)
+        );
+
+        alloc.take(0);
+        assert!(try_subfield!(node, value).is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn refs_compare_by_identity() {
+        let alloc = Allocation::<Fallible>::new(2);
+        let a = alloc.put(0, Fallible { a: 1 });
+        let b = alloc.put(1, Fallible { a: 1 });
+
+        assert!(a == alloc.get(0) && a != b);
+        assert!(a.wrap() == alloc.get(0).wrap() && a.wrap() != b.wrap());
+
+        let set = std::collections::HashSet::from([a.wrap(), b.wrap(), a.wrap()]);
+        assert_eq!(set.len(), 2);
+
+        // A new object in the same slot is a different identity.
+        alloc.take(0);
+        assert!(alloc.put(0, Fallible { a: 1 }) != a);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn debug_shows_liveness_and_value() {
+        let alloc = Allocation::<Fallible>::new(1);
+        let value = alloc.put(0, Fallible { a: 1 });
+        let a = value.wrap().a_prim_ref();
+
+        assert!(format!("{:?}", value.wrap()).starts_with("FallibleRef(Ref { gen: "));
+        assert!(format!("{:?}", a.debug_value()).ends_with("alive: true, value: 1 }"));
+
+        alloc.take(0);
+        assert!(format!("{:?}", a.debug_value()).ends_with("alive: false }"));
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn dangling_refs_are_never_alive() {
+        const DANGLING: Ref<u32> = Ref::dangling();
+
+        let alloc = Allocation::<u32>::new(1);
+        alloc.put(0, 1);
+
+        assert!(!DANGLING.is_alive());
+        assert!(DANGLING.try_read().is_none());
+        assert!(DANGLING.try_destroy().is_none());
+        assert!(!alloc.contains(DANGLING));
+        assert!(Ref::<u32>::find_by_gen(DANGLING.gen()).is_none());
+        assert!(DANGLING.to_thin().resolve().is_none());
+        assert_eq!(
+            alloc
+                .try_put_with_gen(0, DANGLING.gen(), 2)
+                .unwrap_err()
+                .kind,
+            PutErrorKind::InvalidGeneration,
+        );
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn unsized_refs_dispatch_dynamically() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 5);
+
+        let shown = unsize!(value, dyn fmt::Display);
+        assert_eq!(unsafe { (*shown.get()).to_string() }, "5");
+
+        alloc.take(0);
+        assert!(shown.try_get().is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn erased_refs_downcast_to_their_own_type() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 5);
+        let erased = ErasedRef::from(value);
+
+        assert!(erased.downcast::<i32>().is_none());
+        assert_eq!(erased.downcast::<u32>(), Some(value));
+
+        alloc.take(0);
+        assert!(!erased.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn db_lookups_panic_on_foreign_threads() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+        let (thin, id) = (value.to_thin(), value.id());
+
+        let result = std::thread::spawn(move || thin.resolve().is_some()).join();
+        assert!(result.is_err());
+        let result = std::thread::spawn(move || id.resolve::<u32>().is_some()).join();
+        assert!(result.is_err());
+
+        // Dead objects from this thread are still reported as dead.
+        alloc.take(0);
+        assert!(thin.resolve().is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn local_generation_ranges_stay_bounded() {
+        // A fresh thread so that no other test's reservations are in the way.
+        std::thread::spawn(|| {
+            let (request, requests) = std::sync::mpsc::channel::<()>();
+            let (reply, replies) = std::sync::mpsc::channel();
+            let helper = std::thread::spawn(move || {
+                for () in requests {
+                    reply.send(db::gen_many(1)).unwrap();
+                }
+            });
+
+            // Interleaving with another thread leaves a gap between every pair of local ranges.
+            let mut foreign = Vec::new();
+            for _ in 0..200 {
+                db::gen_many(1);
+                request.send(()).unwrap();
+                foreign.push(replies.recv().unwrap());
+            }
+            db::gen_many(1);
+            drop(request);
+            helper.join().unwrap();
+
+            let range_count = || db::use_local_gens(|ranges| ranges.len());
+            assert!(range_count() <= 64);
+
+            // Recent foreign generations are still caught, old ones were merged away.
+            let last = *foreign.last().unwrap();
+            assert!(std::panic::catch_unwind(|| db::assert_local_gen(last)).is_err());
+            db::assert_local_gen(foreign[0]);
+
+            // Slices draw from their arena's own block rather than reserving one at a time.
+            let before = range_count();
+            let mut arena = SliceArena::new();
+            let slices = (0..100).map(|i| arena.push([i])).collect::<Vec<_>>();
+            assert!(range_count() <= before + 1);
+            assert!(slices
+                .windows(2)
+                .all(|w| w[1].gen().get() == w[0].gen().get() + 1));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn find_by_gen_checks_type_and_liveness() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 5);
+
+        assert_eq!(Ref::<u32>::find_by_gen(value.gen()), Some(value));
+        assert!(Ref::<i32>::find_by_gen(value.gen()).is_none());
+
+        assert_eq!(value.id().resolve::<u32>(), Some(value));
+
+        alloc.take(0);
+        assert!(Ref::<u32>::find_by_gen(value.gen()).is_none());
+        assert!(value.id().resolve::<u32>().is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn option_ref_has_no_size_overhead() {
+        assert_eq!(size_of::<Option<Ref<u32>>>(), size_of::<Ref<u32>>());
+        assert_eq!(size_of::<Option<Ref<[u32]>>>(), size_of::<Ref<[u32]>>());
+        assert_eq!(size_of::<Option<FallibleRef>>(), size_of::<FallibleRef>());
+    }
+
+    #[test]
+    fn refs_are_two_words() {
+        assert_eq!(size_of::<Ref<u8>>(), 2 * size_of::<u64>());
+        assert_eq!(size_of::<Option<Ref<u8>>>(), size_of::<Ref<u8>>());
+
+        let mut arena = SliceArena::new();
+        let bytes = arena.push(vec![0u8; 530_000]);
+
+        let near = bytes.index(1001);
+        near.write(1);
+        assert_eq!(bytes.index(1001).read(), 1);
+        assert!(near.is_alive());
+        assert_ne!(near, bytes.index(1000));
+
+        let far = std::panic::catch_unwind(|| bytes.index(528_000));
+        assert!(far.is_err());
+
+        arena.take(bytes);
+        assert!(!near.is_alive());
+    }
+
+    #[repr(transparent)]
+    struct Meters(u32);
+
+    #[derive(Copy, Clone)]
+    struct MetersRef(Ref<Meters>);
+
+    impl RefWrapper for MetersRef {
+        type Pointee = Meters;
+
+        fn from_raw(raw: Ref<Self::Pointee>) -> Self {
+            Self(raw)
+        }
+
+        fn raw(self) -> Ref<Self::Pointee> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn cast_wrapper_round_trips_transparent_newtype() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 5);
+
+        let meters = unsafe { value.cast_wrapper::<MetersRef>() };
+        assert_eq!(unsafe { (*meters.raw().get()).0 }, 5);
+
+        let back = unsafe { meters.raw().try_reinterpret::<u32>() }.unwrap();
+        back.write(6);
+        assert_eq!(value.read(), 6);
+
+        alloc.dealloc();
+        assert!(!meters.raw().is_alive());
+    }
+
+    #[test]
+    #[should_panic = "incompatible layout"]
+    fn cast_wrapper_rejects_incompatible_layout() {
+        let alloc = Allocation::<u64>::new(1);
+        let _ = unsafe { alloc.put(0, 5).cast_wrapper::<MetersRef>() };
+    }
+
+    #[test]
+    fn try_get_contract() {
+        let alloc = Allocation::<u32>::new(2);
+        alloc.put(1, 3);
+
+        assert!(alloc.try_get(2).is_none());
+        assert_eq!(alloc.checked_get(2).err(), Some(SlotError::OutOfRange));
+
+        assert!(alloc.try_get(0).is_none());
+        assert_eq!(alloc.checked_get(0).err(), Some(SlotError::Empty));
+
+        assert_eq!(alloc.try_get(1).map(Ref::read), Some(3));
+        assert_eq!(alloc.checked_get(1).map(Ref::read), Ok(3));
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn grow_keeps_refs_alive_across_pages() {
+        let alloc = Allocation::<u32>::new(1);
+        let first = alloc.put(0, 1);
+        let page_len = alloc.page_len();
+
+        alloc.grow(page_len * 3);
+        assert!(first.is_alive());
+        assert_eq!(alloc.len(), page_len * 3);
+
+        let last = alloc.put(page_len * 3 - 1, 2);
+        assert_eq!(first.read(), 1);
+        assert_eq!(last.read(), 2);
+        assert_eq!(
+            alloc.slot_index(last.gen_ptr().as_ptr()),
+            Some(page_len * 3 - 1)
+        );
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn shrink_to_fit_releases_trailing_pages_in_place() {
+        let alloc = Allocation::<u32>::new(1);
+        let page_len = alloc.page_len();
+        alloc.grow(page_len * 4);
+
+        let kept = alloc.put(page_len + 1, 7);
+        alloc.shrink_to_fit();
+        assert_eq!(alloc.len(), page_len + 2);
+        assert!(kept.is_alive());
+        assert_eq!(kept.read(), 7);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn empty_allocations_keep_separate_metadata() {
+        let a = Allocation::<u32>::new(0);
+        let b = Allocation::<u32>::new(0);
+        a.grow(1);
+        b.grow(1);
+
+        a.put(0, 1);
+        assert_eq!(a.remaining_capacity(), 0);
+        assert_eq!(b.remaining_capacity(), 1);
+
+        a.dealloc();
+        b.dealloc();
+    }
+
+    #[test]
+    fn recycled_header_forgets_interned_keys() {
+        let a = Allocation::<u32>::new(1);
+        a.get_or_insert_by_key(1u8, || 1);
+        a.dealloc();
+
+        let b = Allocation::<u32>::new(1);
+        assert_eq!(b.get_or_insert_by_key(1u8, || 2).read(), 2);
+        b.dealloc();
+    }
+
+    #[test]
+    #[should_panic = "attempted to use a deallocated allocation"]
+    fn double_dealloc_panics() {
+        let alloc = Allocation::<u32>::new(1);
+        alloc.dealloc();
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn stale_refs_stay_dead_after_storage_is_reused() {
+        let a = Allocation::<u32>::new(4);
+        let stale = a.put(0, 1);
+        a.dealloc();
+        assert!(!a.is_alive());
+
+        let b = Allocation::<u32>::new(4);
+        let fresh = b.put(0, 2);
+        assert!(b.is_alive());
+        assert!(!stale.is_alive());
+        assert_eq!(fresh.read(), 2);
+
+        b.dealloc();
+    }
+
+    #[test]
+    fn stale_refs_never_touch_dropped_values() {
+        let a = Allocation::<String>::new(2);
+        let stale = a.put(0, String::from("gone"));
+        a.dealloc();
+
+        // None of these may read the dropped string.
+        assert!(stale.try_with(String::len).is_none());
+        assert!(stale.try_cloned().is_none());
+        assert!(stale.try_update(|value| value.push('!')).is_none());
+
+        // The same storage comes back out of the pool with a fresh value in the stale slot.
+        let b = Allocation::<String>::new(2);
+        let fresh = b.put(0, String::from("fresh"));
+        assert_eq!(fresh.gen_ptr(), stale.gen_ptr());
+        assert!(stale.try_with(String::len).is_none());
+        assert_eq!(fresh.with(String::len), 5);
+        b.dealloc();
+    }
+
+    #[test]
+    fn pooled_pages_drop_every_value_once() {
+        let counter = std::rc::Rc::new(());
+        let alloc = Allocation::<std::rc::Rc<()>>::new(4);
+        alloc.grow(alloc.page_len() * 3);
+
+        let refs = (0..alloc.len())
+            .map(|index| alloc.put(index, counter.clone()))
+            .collect::<Vec<_>>();
+        alloc.dealloc();
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+
+        // Reusing the pooled pages doesn't revive or double-drop anything.
+        let reused = Allocation::<std::rc::Rc<()>>::new(4);
+        reused.grow(reused.page_len() * 3);
+        reused.put(reused.len() - 1, counter.clone());
+        assert!(refs.iter().all(|stale| !stale.is_alive()));
+        assert_eq!(std::rc::Rc::strong_count(&counter), 2);
+
+        reused.dealloc();
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn churn_reuses_pooled_storage() {
+        struct Churned;
+
+        let pooled = || {
+            db::use_free_pool::<Churned, _>(|pool| {
+                let headers = pool.headers.values().map(Vec::len).sum::<usize>();
+                let pages = pool.pages.values().map(Vec::len).sum::<usize>();
+                (headers, pages)
+            })
+        };
+
+        // Lengths sharing a page size share pooled storage.
+        for len in (0..100).map(|i| 100 + i % 7) {
+            let alloc = Allocation::<Churned>::new(len);
+            alloc.put(len - 1, Churned);
+            alloc.dealloc();
+        }
+
+        assert_eq!(pooled(), (1, 0));
+    }
+
+    #[test]
+    fn backend_blocks_stay_out_of_the_pool() {
+        struct Foreign;
+
+        let alloc = Allocation::<Foreign>::try_new_with(4, GlobalStorage).unwrap();
+        alloc.dealloc();
+
+        let pooled = db::use_free_pool::<Foreign, _>(|pool| {
+            pool.headers.values().map(Vec::len).sum::<usize>()
+        });
+        assert_eq!(pooled, 0);
+    }
+
+    #[test]
+    fn allocations_issue_generations_from_their_own_blocks() {
+        let a = Allocation::<u32>::new(2);
+        let b = Allocation::<u32>::new(2);
+
+        let first = a.put(0, 1);
+        b.put(0, 2);
+        let second = a.put(1, 3);
+
+        // Interleaved puts into another allocation don't disturb this one's sequence.
+        assert_eq!(second.gen().get(), first.gen().get() + 1);
+
+        a.dealloc();
+        b.dealloc();
+    }
+
+    #[test]
+    fn slice_projections_check_bounds_and_liveness() {
+        let alloc = Allocation::<[u32; 4]>::new(1);
+        let slice = alloc.put(0, [1, 2, 3, 4]).as_slice();
+
+        let middle = slice.subslice(1..=2);
+        assert_eq!(middle.len(), 2);
+        assert_eq!(middle.index(1).read(), 3);
+        assert!(middle.try_index(2).is_none());
+        assert!(slice.try_subslice(3..5).is_none());
+
+        alloc.take(0);
+        assert!(middle.try_index(0).is_none());
+        assert!(slice.try_subslice(..).is_none());
+        assert_eq!(slice.len(), 4);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn any_allocation_downcasts_to_its_own_type() {
+        let alloc = Allocation::<u32>::new(2);
+        let value = alloc.put(0, 1);
+        let erased = AnyAllocation::from(alloc);
+
+        assert!(erased.downcast::<u64>().is_none());
+        assert_eq!(erased.downcast::<u32>().unwrap().get(0).read(), 1);
+        assert_eq!(erased.len(), 2);
+
+        erased.dealloc();
+        assert!(!erased.is_alive());
+        assert!(!value.is_alive());
+    }
+
+    #[test]
+    #[cfg(feature = "zeroize")]
+    fn taken_values_are_wiped() {
+        let alloc = Allocation::<[u8; 32]>::new(1);
+        let key = alloc.put(0, [0xAA; 32]);
+
+        alloc.take(0);
+        assert_eq!(unsafe { *key.get_unchecked() }, [0; 32]);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn replace_oldest_recycles_in_push_order() {
+        let mut arena = Arena::with_policy(FullPolicy::ReplaceOldest);
+        arena.reserve(2);
+        let capacity = arena.capacity();
+
+        let refs = (0..capacity as u32)
+            .map(|i| arena.push(i))
+            .collect::<Vec<_>>();
+        arena.remove(refs[0]);
+        arena.push(100);
+
+        // The arena is full again so the next push replaces the oldest survivor.
+        let newest = arena.push(101);
+        assert!(!refs[1].is_alive());
+        assert!(refs[2..].iter().all(|value| value.is_alive()));
+        assert_eq!(newest.read(), 101);
+        assert_eq!(arena.capacity(), capacity);
+    }
+
+    #[test]
+    fn replace_oldest_rings_start_with_room() {
+        let mut empty = Arena::with_policy(FullPolicy::ReplaceOldest);
+        assert_eq!(empty.push(1u32).read(), 1);
+        assert!(empty.capacity() > 0);
+
+        let mut ring = Arena::with_capacity_and_policy(2, FullPolicy::ReplaceOldest);
+        let refs = [1u32, 2, 3].map(|i| ring.push(i));
+        assert!(!refs[0].is_alive());
+        assert_eq!(refs[1].read() + refs[2].read(), 5);
+        assert_eq!(ring.capacity(), 2);
+    }
+
+    #[test]
+    fn error_policy_rejects_pushes_when_full() {
+        let mut arena = Arena::with_policy(FullPolicy::Error);
+        assert_eq!(arena.try_push(1u32).err().unwrap().kind, PutErrorKind::Full);
+
+        arena.reserve(1);
+        for i in 0..arena.capacity() as u32 {
+            arena.push(i);
+        }
+        assert_eq!(arena.try_push(1).err().unwrap().value, 1);
+    }
+
+    #[test]
+    fn spawned_objects_can_be_destroyed() {
+        let value = Ref::new(String::from("hello"));
+        let other = Ref::new(String::from("world"));
+
+        assert_eq!(value.destroy(), "hello");
+        assert!(!value.is_alive());
+        assert!(value.try_destroy().is_none());
+        assert_eq!(unsafe { &*other.get() }, "world");
+    }
+
+    #[test]
+    fn refs_destroy_their_own_slot() {
+        let alloc = Allocation::<Node>::new(2);
+        let node = alloc.put(
+            1,
+            Node {
+                value: 1,
+                next: None,
+            },
+        );
+
+        // Projections don't own the slot they point into.
+        assert!(subfield!(node, value).allocation().is_none());
+        assert_eq!(node.allocation().map(|found| found.len()), Some(2));
+
+        assert_eq!(node.destroy().value, 1);
+        assert!(alloc.try_get(1).is_none());
+        assert_eq!(alloc.remaining_capacity(), 2);
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn borrows_exclude_conflicting_access() {
+        let alloc = Allocation::<Node>::new(1);
+        let node = alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: None,
+            },
+        );
+        let value = subfield!(node, value);
+
+        {
+            let shared = node.borrow();
+            assert_eq!(shared.value, 1);
+            assert_eq!(*value.borrow(), 1);
+            assert!(value.try_borrow_mut().is_none());
+        }
+
+        {
+            let mut exclusive = value.borrow_mut();
+            *exclusive += 1;
+            assert!(node.try_borrow().is_none());
+        }
+
+        assert_eq!(value.read(), 2);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn update_runs_in_place_once_alive() {
+        let alloc = Allocation::<String>::new(1);
+        let value = alloc.put(0, String::from("a"));
+
+        assert_eq!(
+            value.update(|value| {
+                value.push('b');
+                value.len()
+            }),
+            2
+        );
+        assert_eq!(*value.borrow(), "ab");
+
+        alloc.take(0);
+        assert!(value.try_update(|value| value.push('c')).is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[should_panic = "attempted to mutably borrow an already borrowed object"]
+    fn nested_updates_conflict() {
+        let alloc = Allocation::<String>::new(1);
+        let value = alloc.put(0, String::from("a"));
+
+        value.update(|outer| value.update(|inner| outer.push_str(inner)));
+    }
+
+    #[test]
+    #[should_panic = "attempted to move or overwrite a borrowed object"]
+    fn objects_cannot_be_taken_inside_with() {
+        let alloc = Allocation::<String>::new(1);
+        let value = alloc.put(0, String::from("a"));
+
+        value.with(|s| {
+            alloc.take(0);
+            s.len()
+        });
+    }
+
+    #[test]
+    fn swap_exchanges_values_but_not_identities() {
+        let (alloc, refs) = Allocation::from_values([String::from("a"), String::from("b")]);
+
+        refs[0].swap(refs[1]);
+        assert_eq!(refs[0].replace(String::from("c")), "b");
+        assert_eq!(*refs[1].borrow(), "a");
+
+        alloc.take(1);
+        assert!(!refs[0].try_swap(refs[1]));
+        assert_eq!(refs[0].cloned(), "c");
+        assert!(refs[1].try_cloned().is_none());
+
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[should_panic = "attempted to move or overwrite a borrowed object"]
+    fn borrowed_objects_cannot_be_taken() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+
+        let _guard = value.borrow();
+        alloc.take(0);
+    }
+
+    #[test]
+    #[should_panic = "attempted to move or overwrite a borrowed object"]
+    fn borrowed_objects_cannot_be_replaced() {
+        let alloc = Allocation::<String>::new(1);
+        let value = alloc.put(0, String::from("a"));
+
+        let guard = value.borrow();
+        value.replace(String::from("b"));
+        drop(guard);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-tools")]
+    #[should_panic = "(type `u32`, generation"]
+    fn dangling_panics_name_the_object() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+        alloc.take(0);
+
+        value.read();
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn refs_serialize_through_id_maps() {
+        let alloc = Allocation::<u32>::new(3);
+        let a = alloc.put(0, 1);
+        let b = alloc.put(1, 2);
+        let dead = alloc.put(2, 3);
+        alloc.take(2);
+
+        let mut ser = SerializeMap::new();
+        let json = ser.scope(|| serde_json::to_string(&[a, b, a, dead]).unwrap());
+        assert_eq!(json, "[1,2,1,0]");
+        assert!(serde_json::to_string(&a).is_err());
+
+        let copies = Allocation::<u32>::new(2);
+        let mut de = DeserializeMap::new();
+        for (index, raw) in ser.refs().iter().enumerate() {
+            let raw = raw.downcast::<u32>().unwrap();
+            de.insert(index as u64 + 1, copies.put(index, raw.read() * 10));
+        }
+
+        let refs = de.scope(|| serde_json::from_str::<Vec<Ref<u32>>>(&json).unwrap());
+        assert_eq!(refs[0], refs[2]);
+        assert_eq!((refs[0].read(), refs[1].read()), (10, 20));
+        assert!(!refs[3].is_alive());
+        assert!(de.scope(|| serde_json::from_str::<Ref<u8>>("1")).is_err());
+        copies.dealloc();
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn strong_handles_destroy_on_last_drop() {
+        let a = Strong::spawn(String::from("hello"));
+        let weak = a.downgrade();
+        let b = Strong::upgrade(weak).unwrap();
+        assert_eq!(a.strong_count(), 2);
+
+        drop(a);
+        assert!(weak.is_alive());
+        assert_eq!(b.strong_count(), 1);
+
+        drop(b);
+        assert!(!weak.is_alive());
+        assert!(Strong::upgrade(weak).is_none());
+        assert!(Strong::upgrade(Ref::new(String::new())).is_none());
+    }
+
+    #[test]
+    fn owned_handles_destroy_on_drop() {
+        let alloc = Allocation::<String>::new(2);
+        let owned = Owned::new(alloc.put(0, String::from("a")));
+        let raw = owned.raw();
+
+        drop(owned);
+        assert!(!raw.is_alive());
+
+        let owned = Owned::new(alloc.put(1, String::from("b")));
+        assert_eq!(owned.into_inner(), "b");
+        assert_eq!(alloc.iter_alive().count(), 0);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn frozen_allocations_hand_out_plain_references() {
+        let alloc = Allocation::<String>::new(3);
+        let a = alloc.put(0, String::from("a"));
+        alloc.put(1, String::from("b"));
+
+        let frozen = alloc.freeze();
+        let value: &String = frozen.read(a).unwrap();
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.take(0))).is_err());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+            || alloc.put(2, String::new())
+        ))
+        .is_err());
+        assert!(a.try_borrow_mut().is_none());
+        assert!(std::panic::catch_unwind(|| a.replace(String::new())).is_err());
+        assert!(std::panic::catch_unwind(|| a.update(|a| a.clear())).is_err());
+        assert!(Token::try_acquire().is_none());
+        assert_eq!(value, "a");
+        assert_eq!(
+            frozen.iter().map(|(_, v)| v.as_str()).collect::<String>(),
+            "ab"
+        );
+
+        drop(frozen);
+        assert_eq!(alloc.take(0).as_deref(), Some("a"));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn tokens_lock_out_other_access_paths() {
+        let alloc = Allocation::<u32>::new(2);
+        let a = alloc.put(0, 1);
+        let b = alloc.put(1, 2);
+
+        let mut token = Token::acquire();
+        *a.get_mut(&mut token) += 10;
+        assert_eq!(a.get_ref(&token) + b.get_ref(&token), 13);
+        assert!(Token::try_acquire().is_none());
+        assert!(std::panic::catch_unwind(|| b.read()).is_err());
+        assert!(std::panic::catch_unwind(|| b.try_set(3)).is_err());
+        assert!(std::panic::catch_unwind(|| b.with(|b| *b)).is_err());
+        assert!(std::panic::catch_unwind(|| *a.borrow()).is_err());
+        assert_eq!(token.unlocked(|| b.read()), 2);
+        assert_eq!(token.unlocked(|| alloc.take(1)), Some(2));
+        assert!(b.try_get_ref(&token).is_none());
+
+        drop(token);
+        assert_eq!(a.read(), 11);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn destroy_hooks_fire_when_slots_empty() {
+        let log = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let alloc = Allocation::<u32>::new(3);
+
+        alloc.on_destroy({
+            let log = log.clone();
+            move |_, &value| log.borrow_mut().push(value)
+        });
+
+        let a = alloc.put(0, 1);
+        a.on_destroy({
+            let log = log.clone();
+            move || log.borrow_mut().push(100)
+        });
+
+        alloc.take(0);
+        alloc.put(1, 2);
+        alloc.put(1, 3);
+        alloc.put(2, 4);
+        alloc.dealloc();
+
+        assert_eq!(*log.borrow(), [100, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn refs_order_by_generation() {
+        let alloc = Allocation::<u32>::new(3);
+        let refs = [alloc.put(2, 0), alloc.put(0, 1), alloc.put(1, 2)];
+        assert!(refs[0].ptr_eq(refs[0]) && !refs[0].ptr_eq(refs[1]));
+
+        let set = refs
+            .iter()
+            .copied()
+            .collect::<std::collections::BTreeSet<_>>();
+        assert!(set.into_iter().eq(refs));
+        assert!(alloc.put(2, 3) > refs[2]);
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "attempted to use a reference into another allocation"]
+    fn foreign_refs_are_caught() {
+        let a = Allocation::<u32>::new(1);
+        let b = Allocation::<u32>::new(1);
+        let value = a.put(0, 1);
+
+        b.take_ref(value);
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn refs_are_method_receivers() {
+        let alloc = Allocation::<Node>::new(2);
+        let tail = alloc.put(
+            1,
+            Node {
+                value: 2,
+                next: None,
+            },
+        );
+        let head = alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: Some(tail),
+            },
+        );
+
+        assert_eq!(head.next(), Some(tail));
+        assert_eq!(tail.next(), None);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn panicking_drops_leave_slots_intact() {
+        struct Bomb(u32);
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                if self.0 == 1 {
+                    panic!("boom");
+                }
+            }
+        }
+
+        let alloc = Allocation::<Bomb>::new(1);
+        let value = alloc.put(0, Bomb(1));
+
+        assert!(std::panic::catch_unwind(|| value.try_set(Bomb(2))).is_err());
+        assert!(value.is_alive());
+        assert_eq!(value.with(|bomb| bomb.0), 2);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn views_hand_out_borrowed_refs() {
+        let mut alloc = Allocation::<u32>::new(3);
+        let mut view = alloc.view();
+        view.put(0, 1);
+        view.put(2, 3);
+
+        for value in view.iter() {
+            value.update(|value| *value *= 10);
+        }
+
+        let indices = view.iter().map(|value| value.index()).collect::<Vec<_>>();
+        assert_eq!(indices, [0, 2]);
+        assert_eq!(view.get(2).map(ViewRef::read), Some(30));
+        assert_eq!(view.take(0), Some(10));
+        assert!(view.get(0).is_none());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn movable_refs_follow_moved_objects() {
+        let alloc = Allocation::<u32>::new(2);
+        let movable = alloc.put(0, 1).to_movable();
+
+        alloc.swap(0, 1);
+        assert!(movable.resolve().is_none());
+
+        let repaired = movable.repair_resolve_prim();
+        assert_eq!(repaired.read(), 1);
+        assert_eq!(alloc.index_of(repaired), Some(1));
+        assert_eq!(movable.resolve(), Some(repaired));
+
+        alloc.take(1);
+        assert!(!movable.repair_resolve_prim().is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn movable_refs_survive_compaction_and_sorting() {
+        let alloc = Allocation::<u32>::new(4);
+        alloc.put(3, 1);
+        let movable = alloc.put(1, 2).to_movable();
+        alloc.put(2, 3);
+
+        alloc.compact();
+        assert_eq!(alloc.index_of(movable.repair_resolve_prim()), Some(0));
+
+        alloc.sort_by_key(|&value| std::cmp::Reverse(value));
+        let repaired = movable.repair_resolve_prim();
+        assert_eq!(alloc.index_of(repaired), Some(1));
+        assert_eq!(repaired.read(), 2);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn thin_refs_are_one_word() {
+        assert_eq!(size_of::<ThinRef<u32>>(), size_of::<u64>());
+        assert_eq!(size_of::<Option<ThinRef<u32>>>(), size_of::<u64>());
+
+        let alloc = Allocation::<u32>::new(2);
+        let thin = alloc.put(0, 1).to_thin();
+        assert_eq!(thin.get().read(), 1);
+
+        alloc.swap(0, 1);
+        assert_eq!(alloc.index_of(thin.get()), Some(1));
+
+        let mistyped = ThinRef::<u64> {
+            gen: thin.gen,
+            _ty: PhantomData,
+        };
+        assert!(mistyped.resolve().is_none());
+
+        alloc.take(1);
+        assert!(!thin.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn compaction_skips_pinned_objects() {
+        let alloc = Allocation::<u32>::new(5);
+        alloc.put(1, 1);
+        let pinned = alloc.put(3, 2).pin();
+        alloc.put(4, 3);
+
+        assert_eq!(alloc.compact(), [(1, 0), (3, 3), (4, 1)]);
+        assert_eq!(alloc.index_of(pinned.raw()), Some(3));
+        assert!(
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.swap(3, 2))).is_err()
+        );
+
+        let unpinned = pinned.unpin();
+        alloc.swap(3, 2);
+        assert_eq!(alloc.try_get(2).map(Ref::read), Some(2));
+        assert!(!unpinned.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn movable_refs_compare_by_identity() {
+        let alloc = Allocation::<Node>::new(4);
+        alloc.put(
+            0,
+            Node {
+                value: 1,
+                next: None,
+            },
+        );
+        alloc.put(
+            1,
+            Node {
+                value: 3,
+                next: None,
+            },
+        );
+        let target = alloc.put(
+            2,
+            Node {
+                value: 2,
+                next: None,
+            },
+        );
+        let whole = target.to_movable();
+        let value = subfield!(target, value).to_movable();
+        let value_twin = subfield!(target, value).to_movable();
+
+        alloc.take(0);
+        alloc.compact();
+        assert_eq!(whole.resolve(), None);
+        assert_eq!(whole, target.to_movable());
+        assert_eq!(value, value_twin);
+        assert_ne!(whole, alloc.get(0).to_movable());
+        assert_eq!(value.try_resolve().map(Ref::read), Some(2));
+        assert!(format!("{whole:?}").contains("alive: true"));
+
+        alloc.take(1);
+        assert_eq!(whole.try_resolve(), None);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn compact_relocating_repairs_internal_refs() {
+        #[derive(Relocate)]
+        enum Link {
+            Tagged {
+                to: Ref<Node>,
+                #[relocate(skip)]
+                tag: Cell<u32>,
+            },
+        }
+
+        let alloc = Allocation::<Node>::new(4);
+        alloc.put(
+            0,
+            Node {
+                value: 0,
+                next: None,
+            },
+        );
+        let tail = alloc.put(
+            2,
+            Node {
+                value: 2,
+                next: None,
+            },
+        );
+        alloc.put(
+            3,
+            Node {
+                value: 3,
+                next: Some(tail),
+            },
+        );
+
+        let mut link = Link::Tagged {
+            to: tail,
+            tag: Cell::new(7),
+        };
+
+        alloc.take(0);
+        assert_eq!(alloc.compact_relocating(), [(2, 0), (3, 1)]);
+        assert!(!tail.is_alive());
+
+        let head = alloc.get(1);
+        let next = head.with(|node| node.next).unwrap();
+        assert_eq!(next.with(|node| node.value), 2);
+
+        link.relocate();
+        let Link::Tagged { to, tag } = &link;
+        assert_eq!(to.with(|node| node.value), 2);
+        assert_eq!(tag.get(), 7);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn slice_arenas_hold_variable_length_slices() {
+        let mut arena = SliceArena::new();
+        let short = arena.push([1u32, 2]);
+        let long = arena.push(0..5u32);
+        let empty = arena.push([]);
+
+        assert_eq!((short.len(), long.len(), empty.len()), (2, 5, 0));
+        assert_eq!(long.index(3).read(), 3);
+        assert_eq!(long.subslice(1..3).with(|values| values.to_vec()), [1, 2]);
+        long.index(4).write(40);
+
+        let middle = long.subslice(1..4);
+        assert_eq!(arena.take(middle), None);
+        assert_eq!(arena.take(long), Some(vec![0, 1, 2, 3, 40]));
+        assert!(!long.is_alive() && !middle.is_alive());
+        assert_eq!(long.try_index(0), None);
+        assert_eq!(arena.take(long), None);
+
+        // The block is reused by the next slice of the same class but stale refs stay dead.
+        let reused = arena.push(5..10u32);
+        assert_eq!(reused.into_raw_parts().0, long.into_raw_parts().0);
+        assert!(!long.is_alive());
+        assert_eq!(arena.len(), 3);
+        assert_eq!(short.index(1).read(), 2);
+    }
+
+    #[test]
+    fn move_to_keeps_handles_resolving() {
+        let from = Allocation::<u32>::new(2);
+        let to = Allocation::<u32>::new(2);
+        let target = from.put(1, 7);
+        let movable = target.to_movable();
+        let thin = target.to_thin();
+
+        let moved = from.move_to(1, to, 0).unwrap();
+        assert!(!target.is_alive());
+        assert_eq!(moved.id(), target.id());
+        assert_eq!(movable.try_resolve(), Some(moved));
+        assert_eq!(thin.get(), moved);
+        assert_eq!(from.remaining_capacity(), 2);
+        assert_eq!(to.remaining_capacity(), 1);
+        assert_eq!(from.move_to(1, to, 1), None);
+
+        let pinned = moved.pin();
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| to.move_to(0, from, 0)));
+        assert!(result.is_err());
+        drop(pinned);
+
+        from.dealloc();
+        to.dealloc();
+    }
+
+    #[test]
+    fn send_movable_refs_resolve_only_on_their_owner() {
+        let alloc = Allocation::<u32>::new(4);
+        alloc.put(0, 1);
+        let target = alloc.put(2, 2);
+        let handle = SendMovableRef::new(target);
+
+        alloc.take(0);
+        alloc.compact();
+        assert_eq!(handle.get().read(), 2);
+        assert_eq!(alloc.index_of(handle.get()), Some(0));
+
+        let result = std::thread::spawn(move || handle.resolve().is_some()).join();
+        assert!(result.is_err());
+
+        alloc.take(0);
+        assert!(!handle.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn batched_liveness_checks() {
+        let alloc = Allocation::<u32>::new(70);
+        let refs = (0..70).map(|i| alloc.put(i, i as u32)).collect::<Vec<_>>();
+        assert!(Ref::all_alive(&refs));
+
+        alloc.take(3);
+        alloc.take(65);
+        assert!(!Ref::all_alive(&refs));
+        assert_eq!(Ref::alive_mask(&refs), [!(1 << 3), (1 << 6) - 1 - (1 << 1)]);
+
+        let (alive, dead) = Ref::partition_alive(&refs);
+        assert_eq!(alive.len(), 68);
+        assert_eq!(dead, [refs[3], refs[65]]);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn pinned_objects_stay_alive() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+
+        let guard = value.pin_alive();
+        assert!(value.try_borrow_mut().is_none());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.take(0))).is_err());
+        assert_eq!(unsafe { *guard.get() }, 1);
+
+        drop(guard);
+        assert_eq!(alloc.take(0), Some(1));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't map files")]
+    fn mmap_storage_refuses_existing_files() {
+        let path = std::env::temp_dir().join(format!("bees-mmap-{}", std::process::id()));
+        std::fs::write(&path, b"precious").unwrap();
+
+        assert!(MmapStorage::create(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"precious");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't map files")]
+    fn mmap_allocations_grow_within_their_file() {
+        let path = std::env::temp_dir().join(format!("bees-mmap-grow-{}", std::process::id()));
+        let alloc =
+            Allocation::<u64>::try_new_with(4, MmapStorage::create(&path).unwrap()).unwrap();
+        let first = alloc.put(0, 1);
+
+        alloc.grow(1000);
+        let last = alloc.put(999, 2);
+        assert_eq!((first.read(), last.read()), (1, 2));
+        assert!(std::fs::metadata(&path).unwrap().len() > 1000 * 8);
+
+        alloc.dealloc();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn iter_values_copies_out_live_values() {
+        let alloc = Allocation::<u32>::new(3);
+        assert_eq!(alloc.iter_values().count(), 0);
+
+        alloc.put(0, 1);
+        let value = alloc.put(2, 3);
+        assert_eq!(alloc.iter_values().collect::<Vec<_>>(), [(0, 1), (2, 3)]);
+
+        let guard = value.borrow_mut();
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.iter_values().count()));
+        assert!(result.is_err());
+        drop(guard);
+
+        alloc.take(0);
+        assert_eq!(alloc.iter_values().collect::<Vec<_>>(), [(2, 3)]);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn try_put_hands_back_out_of_range_values() {
+        let alloc = Allocation::<String>::new(1);
+
+        let err = alloc.try_put(1, String::from("a")).unwrap_err();
+        assert_eq!(err.kind, PutErrorKind::OutOfRange);
+        assert_eq!(err.value, "a");
+        assert_eq!(alloc.remaining_capacity(), 1);
+
+        let value = alloc.try_put(0, err.value).unwrap();
+        assert_eq!(value.cloned(), "a");
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn allocations_can_use_custom_allocators() {
+        let alloc = Allocation::<u32>::new_in(2, allocator_api2::alloc::Global);
+        let value = alloc.put(1, 5);
+        assert_eq!(value.read(), 5);
+        assert_eq!(alloc.take(1), Some(5));
+        assert!(!value.is_alive());
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn custom_allocators_back_every_page() {
+        use allocator_api2::alloc::{AllocError, Allocator, Global};
+
+        struct Counting(std::rc::Rc<Cell<usize>>);
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        struct Tracked(u32);
+
+        let count = std::rc::Rc::new(Cell::new(0));
+        let alloc = Allocation::<Tracked>::new_in(2, Counting(count.clone()));
+        assert_eq!(count.get(), 1);
+
+        alloc.grow(100);
+        let count_after_grow = count.get();
+        assert!(count_after_grow > 1);
+
+        let value = alloc.put(99, Tracked(5));
+        assert_eq!(value.with(|value| value.0), 5);
+        alloc.take(99);
+
+        // Shrinking keeps the pages for the allocation itself rather than pooling them.
+        alloc.shrink_to_fit();
+        let pooled = db::use_free_pool::<Tracked, _>(|pool| pool.pages.len());
+        assert_eq!(pooled, 0);
+
+        alloc.grow(100);
+        assert_eq!(count.get(), count_after_grow);
+        alloc.dealloc();
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    #[should_panic = "allocation size overflow"]
+    fn custom_allocators_reject_overflowing_lengths() {
+        Allocation::<u64>::new_in(usize::MAX, allocator_api2::alloc::Global);
+    }
+
+    #[test]
+    fn get_many_rejects_duplicate_and_empty_slots() {
+        let alloc = Allocation::<u32>::new(3);
+        let a = alloc.put(0, 1);
+        let b = alloc.put(1, 2);
+
+        assert_eq!(alloc.get_many([1, 0]), Some([b, a]));
+        assert_eq!(alloc.get_many([0, 0]), None);
+        assert_eq!(alloc.get_many([0, 2]), None);
+        assert_eq!(alloc.get_many([0, 3]), None);
+        assert_eq!(alloc.get_many([]), Some([]));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn put_with_hands_out_the_future_ref() {
+        let alloc = Allocation::<Node>::new(2);
+
+        let node = alloc.put_with(0, |this| {
+            assert!(!this.is_alive());
+            Node {
+                value: 1,
+                next: Some(this),
+            }
+        });
+        assert_eq!(node.with(|node| node.next), Some(node));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            alloc.put_with(1, |_| panic!("no value"))
+        }));
+        assert!(result.is_err());
+        assert!(!alloc.is_occupied(1));
+        assert_eq!(alloc.remaining_capacity(), 1);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn deferred_takes_drop_on_flush() {
+        struct Counted(&'static Cell<u32>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops: &'static Cell<u32> = Box::leak(Box::new(Cell::new(0)));
+        let alloc = Allocation::<Counted>::new(2);
+        let value = alloc.put(0, Counted(drops));
+
+        assert!(alloc.defer_take(0));
+        assert!(!value.is_alive());
+        assert_eq!(alloc.remaining_capacity(), 2);
+        assert_eq!(drops.get(), 0);
+        assert!(!alloc.defer_take(0));
+        assert!(!alloc.defer_take(1));
+
+        flush_deferred();
+        assert_eq!(drops.get(), 1);
+        flush_deferred();
+        assert_eq!(drops.get(), 1);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn dealloc_into_hands_back_values_in_slot_order() {
+        let alloc = Allocation::<String>::new(3);
+        let a = alloc.put(2, String::from("a"));
+        alloc.put(0, String::from("b"));
+
+        let guard = a.borrow();
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.dealloc_into()));
+        assert!(result.is_err());
+        drop(guard);
+        assert!(a.is_alive());
+
+        assert_eq!(alloc.dealloc_into(), ["b", "a"]);
+        assert!(!a.is_alive());
+    }
+
+    #[test]
+    fn free_slot_queries_track_occupancy() {
+        let alloc = Allocation::<u32>::new(3);
+        assert_eq!(alloc.first_free(), Some(0));
+
+        alloc.put(0, 1);
+        alloc.put(2, 3);
+        assert!(alloc.is_occupied(0) && !alloc.is_occupied(1));
+        assert_eq!(alloc.first_free(), Some(1));
+        assert_eq!(alloc.iter_free().collect::<Vec<_>>(), [1]);
+
+        alloc.put(1, 2);
+        assert_eq!(alloc.first_free(), None);
+        assert_eq!(alloc.iter_free().count(), 0);
+
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| alloc.is_occupied(3)));
+        assert!(result.is_err());
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn duplicate_needs_a_value_and_a_free_slot() {
+        let alloc = Allocation::<String>::new(3);
+        let original = alloc.put(1, String::from("a"));
+
+        let copy = alloc.duplicate(1).unwrap();
+        assert_ne!(copy, original);
+        assert_eq!(copy.cloned(), "a");
+        assert_eq!(original.cloned(), "a");
+
+        assert_eq!(alloc.duplicate(2), None);
+        alloc.duplicate(1).unwrap();
+        assert_eq!(alloc.duplicate(1), None);
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn arenas_collect_and_extend() {
+        let mut arena = (0..20u32).collect::<Arena<_>>();
+        assert_eq!(arena.len_alive(), 20);
+        assert!(arena.capacity() >= 20);
+
+        arena.extend([20, 21]);
+        assert_eq!(arena.len_alive(), 22);
+
+        let value = arena.push(22);
+        assert_eq!(arena.remove(value), Some(22));
+        assert_eq!(arena.len_alive(), 22);
+
+        let mut fixed = Arena::with_policy(FullPolicy::Error);
+        fixed.reserve(1);
+        let capacity = fixed.capacity() as u32;
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| fixed.extend(0..=capacity)));
+        assert!(result.is_err());
+        assert_eq!(fixed.len_alive(), capacity as usize);
+    }
+
+    #[test]
+    fn freezing_fails_on_mutably_borrowed_objects() {
+        let alloc = Allocation::<u32>::new(2);
+        let value = alloc.put(0, 1);
+
+        let guard = value.borrow_mut();
+        assert!(alloc.try_freeze().is_none());
+        drop(guard);
+
+        let frozen = alloc.try_freeze().unwrap();
+        let again = alloc.try_freeze().unwrap();
+        assert_eq!(again.get(0), Some(&1));
+        assert_eq!(frozen.get(1), None);
+        drop((frozen, again));
+
+        assert_eq!(alloc.take(0), Some(1));
+        alloc.dealloc();
+    }
+
+    #[test]
+    fn relocated_refs_follow_moves_only() {
+        let from = Allocation::<u32>::new(1);
+        let to = Allocation::<u32>::new(1);
+        let value = from.put(0, 1);
+
+        assert_eq!(value.relocated(), value);
+        assert_eq!(Ref::<u32>::dangling().relocated(), Ref::dangling());
+
+        let moved = from.move_to(0, to, 0).unwrap();
+        assert_eq!(value.relocated(), moved);
+        assert_eq!(value.relocated().read(), 1);
+
+        to.take(0);
+        assert!(!value.relocated().is_alive());
+        from.dealloc();
+        to.dealloc();
+    }
+
+    #[test]
+    fn try_pin_alive_fails_on_dead_or_mutably_borrowed_objects() {
+        let alloc = Allocation::<u32>::new(1);
+        let value = alloc.put(0, 1);
+
+        let guard = value.borrow_mut();
+        assert!(value.try_pin_alive().is_none());
+        drop(guard);
+
+        let pin = value.try_pin_alive().unwrap();
+        assert_eq!(pin.target(), value);
+        assert!(std::panic::catch_unwind(|| value.replace(2)).is_err());
+        drop(pin);
+
+        alloc.take(0);
+        assert!(value.try_pin_alive().is_none());
+        alloc.dealloc();
     }
 }
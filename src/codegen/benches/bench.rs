@@ -3,6 +3,61 @@ use std::hint::black_box;
 use bees::Allocation;
 use criterion::{criterion_group, criterion_main, Criterion};
 
+#[derive(bees::Struct)]
+struct Node {
+    value: u64,
+    next: Option<NodeRef>,
+}
+
+const LIST_LEN: usize = 1 << 20;
+
+fn build_list(order: &[usize]) -> NodeRef {
+    let alloc = Allocation::new(order.len());
+    let mut next = None;
+
+    for &index in order.iter().rev() {
+        next = Some(
+            alloc
+                .put(
+                    index,
+                    Node {
+                        value: index as u64,
+                        next,
+                    },
+                )
+                .wrap(),
+        );
+    }
+
+    next.unwrap()
+}
+
+fn shuffled_order(len: usize) -> Vec<usize> {
+    let mut order = (0..len).collect::<Vec<_>>();
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+
+    for i in (1..len).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        order.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+
+    order
+}
+
+fn sum_list(head: NodeRef) -> u64 {
+    let mut sum = 0;
+    let mut curr = Some(head);
+
+    while let Some(node) = curr {
+        sum += node.value();
+        curr = node.next();
+    }
+
+    sum
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let alloc = Allocation::new(1);
     let object = alloc.put(0, 4u32);
@@ -10,6 +65,16 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("is alive", |b| b.iter(|| black_box(object).is_alive()));
 
     c.bench_function("read value", |b| b.iter(|| black_box(object).read()));
+
+    let contiguous = build_list(&(0..LIST_LEN).collect::<Vec<_>>());
+    c.bench_function("traverse contiguous list", |b| {
+        b.iter(|| sum_list(black_box(contiguous)))
+    });
+
+    let shuffled = build_list(&shuffled_order(LIST_LEN));
+    c.bench_function("traverse shuffled list", |b| {
+        b.iter(|| sum_list(black_box(shuffled)))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
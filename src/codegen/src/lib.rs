@@ -9,10 +9,24 @@ pub struct Example {
 
 impl ExampleRef {
     pub fn increment(self) {
-        self.set_a(self.a() + 1);
-        self.set_b(self.b() + 1);
-        self.set_c(self.c() + 1);
-        self.set_d(self.d() + 1);
-        self.set_e(self.e() + 1);
+        self.bees_modify(|this| {
+            this.a += 1;
+            this.b += 1;
+            this.c += 1;
+            this.d += 1;
+            this.e += 1;
+        });
+    }
+}
+
+#[derive(bees::Struct)]
+#[bees(fallible)]
+pub struct FallibleExample {
+    a: u32,
+}
+
+impl FallibleExampleRef {
+    pub fn increment(self) -> bool {
+        self.bees_modify(|this| this.a += 1).is_some()
     }
 }